@@ -0,0 +1,90 @@
+use std::sync::Arc;
+
+use crate::{exec::State, builtins::BuiltinGroup, loader::ScriptLoader, profile::Profile};
+
+/// Configures a `State` before it is made, as returned by `State::builder`.
+/// Starts from every builtin group and no library.
+#[derive(Debug)]
+pub struct StateBuilder {
+  profile: Profile,
+  loader: Option<Arc<dyn ScriptLoader>>,
+  lib: bool,
+  lib_path: Option<String>,
+}
+
+impl State {
+  pub fn builder() -> StateBuilder {
+    StateBuilder {
+      profile: Profile::trusted(),
+      loader: None,
+      lib: false,
+      lib_path: None,
+    }
+  }
+}
+
+impl StateBuilder {
+  /// Loads only these builtin groups. `Core` is always loaded.
+  pub fn groups(mut self, groups: &[BuiltinGroup]) -> StateBuilder {
+    self.profile.groups = vec![BuiltinGroup::Core];
+    for group in groups {
+      self.profile = self.profile.allow_group(*group);
+    }
+    self
+  }
+
+  pub fn group(mut self, group: BuiltinGroup) -> StateBuilder {
+    self.profile = self.profile.allow_group(group);
+    self
+  }
+
+  pub fn without_group(mut self, group: BuiltinGroup) -> StateBuilder {
+    if group != BuiltinGroup::Core {
+      self.profile.groups.retain(|loaded| *loaded != group);
+    }
+    self
+  }
+
+  /// Replaces the whole profile, groups included.
+  pub fn profile(mut self, profile: Profile) -> StateBuilder {
+    self.profile = profile;
+    self
+  }
+
+  pub fn loader(mut self, loader: impl ScriptLoader + 'static) -> StateBuilder {
+    self.loader = Some(Arc::new(loader));
+    self
+  }
+
+  /// Whether to run the standard library once the state is made.
+  pub fn lib(mut self, lib: bool) -> StateBuilder {
+    self.lib = lib;
+    self
+  }
+
+  /// Runs the standard library the loader reads from `path` once the
+  /// state is made, instead of the bundled one. The bundled one is run if
+  /// the loader can't read it.
+  pub fn lib_path(mut self, path: &str) -> StateBuilder {
+    self.lib = true;
+    self.lib_path = Some(path.to_string());
+    self
+  }
+
+  pub fn build(self) -> State {
+    let mut state = State::with_profile(self.profile);
+    if let Some(loader) = self.loader {
+      state.loader = loader;
+    }
+    if self.lib {
+      let loaded = match &self.lib_path {
+        Some(path) => state.load_lib_from(path).is_ok(),
+        None => false,
+      };
+      if !loaded {
+        state.load_lib();
+      }
+    }
+    state
+  }
+}
@@ -1,51 +1,195 @@
-
 use std::collections::HashMap;
 
-use crate::{val::{Val, p_all}, exec::State, object::read_string};
+use crate::{val::Val, exec::State, object::read_string, record::Record};
+
+#[cfg(feature = "debug")]
+mod debug;
+#[cfg(feature = "events")]
+mod events;
+#[cfg(feature = "generators")]
+mod generators;
+#[cfg(feature = "host")]
+mod host;
+#[cfg(feature = "io")]
+mod io;
+#[cfg(feature = "lists")]
+mod lists;
+#[cfg(feature = "math")]
+mod math;
+#[cfg(feature = "modules")]
+pub(crate) mod modules;
+#[cfg(feature = "strings")]
+mod strings;
+#[cfg(feature = "timers")]
+mod timers;
 
 pub type Callback = fn(args: Vec<Val>, &mut State);
 
+/// A set of builtins that can be installed into a `State` together. Every
+/// group but `Core` has a cargo feature of the same name, and is empty
+/// when the feature is off.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum BuiltinGroup {
+  /// Special forms, control flow, type predicates and equality.
+  Core,
+  Math,
+  Strings,
+  Lists,
+  /// Reading scripts with `load`.
+  Io,
+  /// Printing and inspecting the interpreter.
+  Debug,
+  /// Builtins that act on the host's programs, such as `set-program`.
+  Host,
+  /// `generator` and `yield`.
+  Generators,
+  /// Subscribing to host events with `on` and `off`.
+  Events,
+  /// `after`, `every` and `cancel-timer`.
+  Timers,
+  /// `module` and `require`.
+  Modules,
+}
+
+impl BuiltinGroup {
+  pub const ALL: [BuiltinGroup; 11] = [
+    BuiltinGroup::Core,
+    BuiltinGroup::Math,
+    BuiltinGroup::Strings,
+    BuiltinGroup::Lists,
+    BuiltinGroup::Io,
+    BuiltinGroup::Debug,
+    BuiltinGroup::Host,
+    BuiltinGroup::Generators,
+    BuiltinGroup::Events,
+    BuiltinGroup::Timers,
+    BuiltinGroup::Modules,
+  ];
+
+  /// Whether the group's builtins were compiled in.
+  pub fn is_enabled(self) -> bool {
+    match self {
+      BuiltinGroup::Core => true,
+      BuiltinGroup::Math => cfg!(feature = "math"),
+      BuiltinGroup::Strings => cfg!(feature = "strings"),
+      BuiltinGroup::Lists => cfg!(feature = "lists"),
+      BuiltinGroup::Io => cfg!(feature = "io"),
+      BuiltinGroup::Debug => cfg!(feature = "debug"),
+      BuiltinGroup::Host => cfg!(feature = "host"),
+      BuiltinGroup::Generators => cfg!(feature = "generators"),
+      BuiltinGroup::Events => cfg!(feature = "events"),
+      BuiltinGroup::Timers => cfg!(feature = "timers"),
+      BuiltinGroup::Modules => cfg!(feature = "modules"),
+    }
+  }
+
+  /// The names of the group's builtins, known even when the group is
+  /// compiled out so scripts using them are denied rather than left with
+  /// unbound symbols. Empty for `Core`, which is always compiled in.
+  pub fn names(self) -> &'static [&'static str] {
+    match self {
+      BuiltinGroup::Core => &[],
+      BuiltinGroup::Math => &[
+        "+", "-", "*", "/", "%", "<", ">", "<=", ">=", "sqrt", "expt", "sin", "cos", "atan2",
+        "floor", "ceiling", "round", "truncate", "abs", "min", "max", "clamp", "lerp",
+        "random", "random-int", "random-choice", "shuffle", "vec2", "ivec2", "vec2?",
+        "vec2-x", "vec2-y", "v+", "v-", "v*", "dot", "v-length", "normalize", "distance",
+        "manhattan",
+      ],
+      BuiltinGroup::Strings => &[
+        "format", "string-length", "string-cons", "string-head", "string-tail", "substring",
+        "string-index", "string-split", "string-join", "string-upcase", "string-downcase",
+        "string-trim", "string-replace", "string-prefix?", "string-suffix?", "string->list",
+        "string-ref", "string", "list->string", "char?", "char->integer", "integer->char",
+        "char-alphabetic?", "char-numeric?", "char-whitespace?", "char-upcase", "char-downcase",
+        "number->string", "string->number", "symbol->string", "string->symbol", "read-from-string",
+      ],
+      BuiltinGroup::Lists => &[
+        "car", "cdr", "cons", "length", "map", "for-each", "filter", "fold", "sort", "append", "reverse",
+        "nth", "member", "assoc", "range", "obj-get", "obj-set", "obj-has?", "obj-keys", "obj-merge",
+      ],
+      BuiltinGroup::Io => &["load"],
+      BuiltinGroup::Debug => &["print", "memory-usage"],
+      BuiltinGroup::Host => &["set-program"],
+      BuiltinGroup::Generators => &["generator", "generator-done?", "generator-close", "yield"],
+      BuiltinGroup::Events => &["on", "off"],
+      BuiltinGroup::Timers => &["after", "every", "cancel-timer"],
+      BuiltinGroup::Modules => &["module", "require"],
+    }
+  }
+}
+
 pub fn get_builtins() -> HashMap<String, Val> {
   let mut builtins = HashMap::new();
+  for group in BuiltinGroup::ALL {
+    builtins.extend(get_builtin_group(group));
+  }
+  builtins
+}
+
+pub fn get_builtin_group(group: BuiltinGroup) -> HashMap<String, Val> {
+  let mut builtins = HashMap::new();
+  match group {
+    BuiltinGroup::Core => core_builtins(&mut builtins),
+    #[cfg(feature = "math")]
+    BuiltinGroup::Math => math::register(&mut builtins),
+    #[cfg(feature = "strings")]
+    BuiltinGroup::Strings => strings::register(&mut builtins),
+    #[cfg(feature = "lists")]
+    BuiltinGroup::Lists => lists::register(&mut builtins),
+    #[cfg(feature = "io")]
+    BuiltinGroup::Io => io::register(&mut builtins),
+    #[cfg(feature = "debug")]
+    BuiltinGroup::Debug => debug::register(&mut builtins),
+    #[cfg(feature = "host")]
+    BuiltinGroup::Host => host::register(&mut builtins),
+    #[cfg(feature = "generators")]
+    BuiltinGroup::Generators => generators::register(&mut builtins),
+    #[cfg(feature = "events")]
+    BuiltinGroup::Events => events::register(&mut builtins),
+    #[cfg(feature = "timers")]
+    BuiltinGroup::Timers => timers::register(&mut builtins),
+    #[cfg(feature = "modules")]
+    BuiltinGroup::Modules => modules::register(&mut builtins),
+    #[allow(unreachable_patterns)]
+    _ => {},
+  }
+  builtins
+}
 
+fn core_builtins(builtins: &mut HashMap<String, Val>) {
   builtins.insert("quote".to_string(), Val::Builtin(true, quote_cb));
   builtins.insert("lambda".to_string(), Val::Builtin(true, lambda_cb));
   builtins.insert("define".to_string(), Val::Builtin(true, define_cb));
   builtins.insert("define-syntax".to_string(), Val::Builtin(true, define_syntax_cb));
   builtins.insert("eval".to_string(), Val::Builtin(false, eval_cb));
   builtins.insert("if".to_string(), Val::Builtin(true, if_cb));
-
-  builtins.insert("load".to_string(), Val::Builtin(false, load_cb));
-  builtins.insert("car".to_string(), Val::Builtin(false, car_cb));
-  builtins.insert("cdr".to_string(), Val::Builtin(false, cdr_cb));
-  builtins.insert("cons".to_string(), Val::Builtin(false, cons_cb));
+  builtins.insert("and".to_string(), Val::Builtin(true, and_cb));
+  builtins.insert("or".to_string(), Val::Builtin(true, or_cb));
+  builtins.insert("when".to_string(), Val::Builtin(true, when_cb));
+  builtins.insert("unless".to_string(), Val::Builtin(true, unless_cb));
+  builtins.insert("case".to_string(), Val::Builtin(true, case_cb));
   builtins.insert("do".to_string(), Val::Builtin(false, do_cb));
-  builtins.insert("+".to_string(), Val::Builtin(false, plus_cb));
-  builtins.insert("-".to_string(), Val::Builtin(false, minus_cb));
-  builtins.insert("*".to_string(), Val::Builtin(false, mult_cb));
-  builtins.insert("/".to_string(), Val::Builtin(false, div_cb));
-  builtins.insert("%".to_string(), Val::Builtin(false, modulo_cb));
   builtins.insert("=".to_string(), Val::Builtin(false, eq_cb));
-  builtins.insert("<".to_string(), Val::Builtin(false, less_cb));
-  builtins.insert(">".to_string(), Val::Builtin(false, greater_cb));
-  builtins.insert("<=".to_string(), Val::Builtin(false, less_eq_cb));
-  builtins.insert(">=".to_string(), Val::Builtin(false, greater_eq_cb));
   builtins.insert("list?".to_string(), Val::Builtin(false, type_list_cb));
   builtins.insert("symbol?".to_string(), Val::Builtin(false, type_sym_cb));
   builtins.insert("string?".to_string(), Val::Builtin(false, type_string_cb));
   builtins.insert("number?".to_string(), Val::Builtin(false, type_num_cb));
   builtins.insert("lambda?".to_string(), Val::Builtin(false, type_lambda_cb));
+  builtins.insert("define-record".to_string(), Val::Builtin(true, define_record_cb));
+  builtins.insert("record?".to_string(), Val::Builtin(false, type_record_cb));
+  builtins.insert("record->object".to_string(), Val::Builtin(false, record_to_object_cb));
+  builtins.insert("object->record".to_string(), Val::Builtin(false, object_to_record_cb));
   builtins.insert("not".to_string(), Val::Builtin(false, not_cb));
   builtins.insert("apply".to_string(), Val::Builtin(false, apply_cb));
-  builtins.insert("format".to_string(), Val::Builtin(false, format_cb));
-  builtins.insert("set-program".to_string(), Val::Builtin(false, set_program_cb));
-
-  builtins.insert("string-length".to_string(), Val::Builtin(false, string_length_cb));
-  builtins.insert("string-cons".to_string(), Val::Builtin(false, string_cons_cb));
-  builtins.insert("string-head".to_string(), Val::Builtin(false, string_head_cb));
-  builtins.insert("string-tail".to_string(), Val::Builtin(false, string_tail_cb));
-
-  builtins
+  builtins.insert("call/cc".to_string(), Val::Builtin(false, call_cc_cb));
+  builtins.insert("call/ec".to_string(), Val::Builtin(false, call_ec_cb));
+  builtins.insert("unwind-protect".to_string(), Val::Builtin(true, unwind_protect_cb));
+  builtins.insert("dynamic-wind".to_string(), Val::Builtin(false, dynamic_wind_cb));
+  builtins.insert("error".to_string(), Val::Builtin(false, error_cb));
+  builtins.insert("catch-cancel".to_string(), Val::Builtin(true, catch_cancel_cb));
+  builtins.insert("without-interrupts".to_string(), Val::Builtin(true, without_interrupts_cb));
+  builtins.insert("current-interrupt".to_string(), Val::Builtin(false, current_interrupt_cb));
 }
 
 fn quote_cb(args: Vec<Val>, state: &mut State) {
@@ -63,6 +207,9 @@ fn lambda_cb(args: Vec<Val>, state: &mut State) {
   state.return_stackframe(val);
 }
 
+/// `(define name value)` binds a value, and `(define (name params...) body...)`
+/// a function. Every form of the body is kept and run in order, the last
+/// giving the value, as with `lambda`.
 fn define_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
     state.return_stackframe(Val::nil());
@@ -118,11 +265,12 @@ fn define_cb(args: Vec<Val>, state: &mut State) {
         //   val.clone(),
         // ]);
       } else {
-        val = Val::Lambda(false, state.get_var_ref(), vec![
+        let mut lambda = vec![
           Val::Sym("lambda".to_string()),
           Val::List(calllist[1..].to_vec()),
-          val.clone(),
-        ]);
+        ];
+        lambda.extend(args[1..].iter().cloned());
+        val = Val::Lambda(false, state.get_var_ref(), lambda);
 
         let first = calllist[0].clone();
         if let Val::Sym(sym) = first {
@@ -219,74 +367,6 @@ fn define_syntax_cb(args: Vec<Val>, state: &mut State) {
   state.return_stackframe(Val::nil());
 }
 
-fn load_cb(args: Vec<Val>, state: &mut State) {
-  println!("load_cb: {:?}", args);
-  if args.is_empty() {
-    state.return_stackframe(Val::nil());
-    return;
-  }
-
-  let filename = if let Val::String(filename) = &args[0] {
-    filename
-  } else {
-    println!("Could not load file: {:?}", args[0]);
-    state.return_stackframe(Val::nil());
-    return;
-  };
-
-  println!("Loading file: {}", filename);
-
-  let file = std::fs::read_to_string(filename);
-  if let Ok(file) = file {
-    let val = p_all(&file);
-    println!("file loaded: {:?}", val);
-    state.replace_stackframe(val);
-  } else {
-    println!("Could not load file: {}", filename);
-    state.return_stackframe(Val::nil());
-  }
-}
-
-fn car_cb(args: Vec<Val>, state: &mut State) {
-  if args.is_empty() {
-    state.return_stackframe(Val::nil());
-  } else if let Val::List(list) = &args[0] {
-    if list.is_empty() {
-      state.return_stackframe(Val::nil());
-    } else {
-      state.return_stackframe(list[0].clone());
-    }
-  } else {
-    state.return_stackframe(Val::nil());
-  }
-}
-
-fn cdr_cb(args: Vec<Val>, state: &mut State) {
-  if args.is_empty() {
-    state.return_stackframe(Val::nil());
-  } else if let Val::List(list) = &args[0] {
-    if list.is_empty() {
-      state.return_stackframe(Val::nil());
-    } else {
-      state.return_stackframe(Val::List(list[1..].to_vec()));
-    }
-  } else {
-    state.return_stackframe(Val::nil());
-  }
-}
-
-fn cons_cb(args: Vec<Val>, state: &mut State) {
-  if args.is_empty() {
-    state.return_stackframe(Val::nil());
-  } else if let Val::List(list) = &args[1] {
-    let mut new_list = vec![args[0].clone()];
-    new_list.extend(list.clone());
-    state.return_stackframe(Val::List(new_list));
-  } else {
-    state.return_stackframe(Val::nil());
-  }
-}
-
 pub(crate) fn do_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
     state.return_stackframe(Val::nil());
@@ -353,14 +433,24 @@ fn if_cb(args: Vec<Val>, state: &mut State) {
     }
   };
 
+  tail_eval(val, state);
+}
+
+/// Evaluates `val` as the value of the current frame. Lists replace the
+/// frame rather than pushing a new one, so special forms stay tail-call
+/// friendly. Symbols read as they do anywhere else a form is evaluated,
+/// so unbound ones are returned as they are.
+pub(crate) fn tail_eval(val: Val, state: &mut State) {
   match &val {
     Val::List(list) => {
       state.replace_stackframe(list.clone());
     },
 
     Val::Sym(sym) => {
-      if let Some(val) = state.get_var(&sym) {
+      if let Some(val) = state.get_var(sym) {
         state.return_stackframe(val.clone());
+      } else if state.is_denied(sym) {
+        state.error(&format!("Capability denied: {}", sym));
       } else {
         state.return_stackframe(val.clone());
       }
@@ -372,239 +462,172 @@ fn if_cb(args: Vec<Val>, state: &mut State) {
   }
 }
 
-fn plus_cb(args: Vec<Val>, state: &mut State) {
-  let mut sum = 0.0;
-  for arg in args {
-    match arg {
-      Val::Num(num) => sum += num,
-      _ => {}
-    }
+/// Reads a symbol in a special form, where unbound symbols read as nil.
+/// Returns `None` after raising an error if the name is denied.
+fn special_sym(sym: &str, state: &mut State) -> Option<Val> {
+  match state.get_var(&sym.to_string()) {
+    Some(val) => Some(val.clone()),
+    None if state.is_denied(sym) => {
+      state.error(&format!("Capability denied: {}", sym));
+      None
+    },
+    None => Some(Val::nil()),
   }
-  state.return_stackframe(Val::Num(sum));
 }
 
-fn minus_cb(args: Vec<Val>, state: &mut State) {
-  if args.is_empty() {
-    state.return_stackframe(Val::Num(0.0));
-    return;
-  }
-
-  let mut sum = if let Val::Num(num) = args[0] {
-    num
-  } else {
-    0.0
-  };
-
-  if args.len() == 1 {
-    state.return_stackframe(Val::Num(-sum));
-    return;
+/// Evaluates argument `index` of a special form. Arguments before the
+/// frame's pc have already been evaluated. Returns `None` after pushing a
+/// stackframe for a list, or raising an error; the special form is called
+/// again once it returns. Unbound symbols read as nil, as in the test of
+/// `if`.
+fn special_arg(index: usize, state: &mut State) -> Option<Val> {
+  let frame = state.get_stackframe();
+  if frame.pc > index {
+    return Some(frame.accum[index].clone());
   }
 
-  for arg in args[1..].iter() {
-    match arg {
-      Val::Num(num) => sum -= num,
-      _ => {}
-    }
-  }
-  state.return_stackframe(Val::Num(sum));
-}
+  let val = match frame.accum[index].clone() {
+    Val::List(list) if !list.is_empty() => {
+      frame.pc = index;
+      state.add_stackframe(list);
+      return None;
+    },
 
-fn mult_cb(args: Vec<Val>, state: &mut State) {
-  let mut sum = 1.0;
-  for arg in args {
-    match arg {
-      Val::Num(num) => sum *= num,
-      _ => {}
-    }
-  }
-  state.return_stackframe(Val::Num(sum));
-}
+    Val::Sym(sym) => {
+      special_sym(&sym, state)?
+    },
 
-fn div_cb(args: Vec<Val>, state: &mut State) {
-  if args.is_empty() {
-    state.return_stackframe(Val::Num(0.0));
-    return;
-  }
-  
-  let mut sum = if let Val::Num(num) = args[0] {
-    num
-  } else {
-    0.0
+    val => val,
   };
 
-  if args.len() == 1 {
-    state.return_stackframe(Val::Num(1.0 / sum));
-    return;
-  }
+  let frame = state.get_stackframe();
+  frame.accum[index] = val.clone();
+  frame.pc = index + 1;
+  Some(val)
+}
 
-  for arg in args[1..].iter() {
-    match arg {
-      Val::Num(num) => sum /= num,
-      _ => {}
-    }
-  }
-  state.return_stackframe(Val::Num(sum));
+fn do_body(body: &[Val]) -> Val {
+  let mut list = vec![Val::Sym("do".to_string())];
+  list.extend(body.iter().cloned());
+  Val::List(list)
 }
 
-fn modulo_cb(args: Vec<Val>, state: &mut State) {
+fn and_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
-    state.return_stackframe(Val::Num(0.0));
-    return;
-  }
-  let mut sum = if let Val::Num(num) = args[0] {
-    num
-  } else {
-    0.0
-  };
-
-  if args.len() == 1 {
-    state.return_stackframe(Val::Num(sum));
+    state.return_stackframe(Val::truth());
     return;
   }
 
-  for arg in args[1..].iter() {
-    match arg {
-      Val::Num(num) => sum %= num,
-      _ => {}
+  // start at the argument whose value may have just been returned
+  let start = state.get_stackframe().pc.max(2) - 1;
+  for i in start..args.len() {
+    let val = match special_arg(i, state) {
+      Some(val) => val,
+      None => return,
+    };
+    if val.is_nil() {
+      state.return_stackframe(val);
+      return;
     }
   }
-  state.return_stackframe(Val::Num(sum));
+
+  tail_eval(args[args.len() - 1].clone(), state);
 }
 
-fn eq_cb(args: Vec<Val>, state: &mut State) {
-  if args.len() < 2 {
-    state.return_stackframe(Val::truth());
+fn or_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
     return;
   }
 
-  let val = args[0].clone();
-  for arg in args[1..].iter() {
-    if val != *arg {
-      state.return_stackframe(Val::nil());
+  // start at the argument whose value may have just been returned
+  let start = state.get_stackframe().pc.max(2) - 1;
+  for i in start..args.len() {
+    let val = match special_arg(i, state) {
+      Some(val) => val,
+      None => return,
+    };
+    if !val.is_nil() {
+      state.return_stackframe(val);
       return;
     }
   }
 
-  state.return_stackframe(Val::truth());
+  tail_eval(args[args.len() - 1].clone(), state);
 }
 
-fn greater_cb(args: Vec<Val>, state: &mut State) {
-  if args.len() == 0 {
-    state.return_stackframe(Val::lies());
-    return;
-  } else if args.len() == 1 {
-    state.return_stackframe(Val::truth());
+fn when_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
     return;
   }
 
-  let mut sum = if let Val::Num(num) = args[0] {
-    num
-  } else {
-    0.0
-  };
-
-  for arg in args[1..].iter() {
-    match arg {
-      Val::Num(num) => {
-        if sum <= *num {
-          state.return_stackframe(Val::lies());
-          return;
-        }
-        sum = *num;
-      },
-      _ => {}
-    }
+  match special_arg(1, state) {
+    Some(cond) if !cond.is_nil() => tail_eval(do_body(&args[1..]), state),
+    Some(_) => state.return_stackframe(Val::nil()),
+    None => {},
   }
-  state.return_stackframe(Val::truth());
 }
 
-fn less_cb(args: Vec<Val>, state: &mut State) {
-  if args.len() == 0 {
+fn unless_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
     state.return_stackframe(Val::nil());
     return;
-  } else if args.len() == 1 {
-    state.return_stackframe(Val::truth());
-    return;
   }
 
-  let mut sum = if let Val::Num(num) = args[0] {
-    num
-  } else {
-    0.0
-  };
-
-  for arg in args[1..].iter() {
-    match arg {
-      Val::Num(num) => {
-        if sum >= *num {
-          state.return_stackframe(Val::lies());
-          return;
-        }
-        sum = *num;
-      },
-      _ => {}
-    }
+  match special_arg(1, state) {
+    Some(cond) if cond.is_nil() => tail_eval(do_body(&args[1..]), state),
+    Some(_) => state.return_stackframe(Val::nil()),
+    None => {},
   }
-  state.return_stackframe(Val::truth());
 }
 
-fn greater_eq_cb(args: Vec<Val>, state: &mut State) {
-  if args.len() == 0 {
-    state.return_stackframe(Val::lies());
-    return;
-  } else if args.len() == 1 {
-    state.return_stackframe(Val::truth());
+/// `(case key ((datum...) body...) (else body...))` compares the evaluated
+/// key against the unevaluated data of each clause.
+fn case_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
     return;
   }
 
-  let mut sum = if let Val::Num(num) = args[0] {
-    num
-  } else {
-    0.0
+  let key = match special_arg(1, state) {
+    Some(key) => key,
+    None => return,
   };
 
-  for arg in args[1..].iter() {
-    match arg {
-      Val::Num(num) => {
-        if sum < *num {
-          state.return_stackframe(Val::lies());
-          return;
-        }
-        sum = *num;
-      },
-      _ => {}
+  for clause in args[1..].iter() {
+    let clause = match clause {
+      Val::List(clause) if !clause.is_empty() => clause,
+      _ => continue,
+    };
+    let matches = match &clause[0] {
+      Val::Sym(sym) if sym == "else" => true,
+      Val::List(data) => data.contains(&key),
+      datum => *datum == key,
+    };
+    if matches {
+      tail_eval(do_body(&clause[1..]), state);
+      return;
     }
   }
-  state.return_stackframe(Val::truth());
+
+  state.return_stackframe(Val::nil());
 }
 
-fn less_eq_cb(args: Vec<Val>, state: &mut State) {
-  if args.len() == 0 {
-    state.return_stackframe(Val::lies());
-    return;
-  } else if args.len() == 1 {
+fn eq_cb(args: Vec<Val>, state: &mut State) {
+  if args.len() < 2 {
     state.return_stackframe(Val::truth());
     return;
   }
 
-  let mut sum = if let Val::Num(num) = args[0] {
-    num
-  } else {
-    0.0
-  };
-
+  let val = args[0].clone();
   for arg in args[1..].iter() {
-    match arg {
-      Val::Num(num) => {
-        if sum > *num {
-          state.return_stackframe(Val::lies());
-          return;
-        }
-        sum = *num;
-      },
-      _ => {}
+    if val != *arg {
+      state.return_stackframe(Val::nil());
+      return;
     }
   }
+
   state.return_stackframe(Val::truth());
 }
 
@@ -648,6 +671,79 @@ fn type_num_cb(args: Vec<Val>, state: &mut State) {
   }
 }
 
+/// `(define-record point (x y))` defines `make-point`, `point?`,
+/// `point-x` and `point-y`. See `State::define_record`.
+fn define_record_cb(args: Vec<Val>, state: &mut State) {
+  let (Some(Val::Sym(name)), Some(Val::List(fields))) = (args.first(), args.get(1)) else {
+    state.error("define-record expects a name and a list of fields");
+    return;
+  };
+  let fields = fields.iter().map(|field| match field {
+    Val::Sym(field) => Some(field.to_string()),
+    _ => None,
+  }).collect::<Option<Vec<String>>>();
+  match fields {
+    Some(fields) => {
+      state.define_record(name, &fields);
+      state.return_stackframe(Val::Sym(name.to_string()));
+    },
+    None => state.error("define-record fields must be symbols"),
+  }
+}
+
+// (record-new name fields values)
+pub(crate) fn record_new_cb(args: Vec<Val>, state: &mut State) {
+  let (Val::Sym(name), Val::List(fields), Val::List(values)) = (&args[0], &args[1], &args[2]) else { return };
+  if fields.len() != values.len() {
+    state.error(&format!("make-{} expects {} arguments, got {}", name, fields.len(), values.len()));
+    return;
+  }
+  let fields = fields.iter().map(read_string).zip(values.iter().cloned()).collect();
+  state.return_stackframe(Val::Record(Record { name: name.to_string(), fields }));
+}
+
+// (record-is value name)
+pub(crate) fn record_is_cb(args: Vec<Val>, state: &mut State) {
+  let is_record = matches!((&args[0], &args[1]), (Val::Record(record), Val::Sym(name)) if &record.name == name);
+  state.return_stackframe(if is_record { Val::truth() } else { Val::lies() });
+}
+
+// (record-field name field record)
+pub(crate) fn record_field_cb(args: Vec<Val>, state: &mut State) {
+  let (Val::Sym(name), Val::Sym(field)) = (&args[0], &args[1]) else { return };
+  match &args[2] {
+    Val::Record(record) if &record.name == name => {
+      state.return_stackframe(record.get(field).cloned().unwrap_or_default());
+    },
+    other => state.error(&format!("{}-{} expects a {}, got {}", name, field, name, other.to_string())),
+  }
+}
+
+fn type_record_cb(args: Vec<Val>, state: &mut State) {
+  let is_record = matches!(args.first(), Some(Val::Record(_)));
+  state.return_stackframe(if is_record { Val::truth() } else { Val::lies() });
+}
+
+fn record_to_object_cb(args: Vec<Val>, state: &mut State) {
+  match args.first() {
+    Some(Val::Record(record)) => state.return_stackframe(record.to_object()),
+    _ => state.error("record->object expects a record"),
+  }
+}
+
+/// `(object->record 'point object)` makes a point from the fields of an
+/// object. See `Record::from_object`.
+fn object_to_record_cb(args: Vec<Val>, state: &mut State) {
+  let record = match (args.first(), args.get(1)) {
+    (Some(Val::Sym(name)), Some(object)) => state.record_from_object(name, object),
+    _ => Err("object->record expects a record type and an object".to_string()),
+  };
+  match record {
+    Ok(record) => state.return_stackframe(record),
+    Err(err) => state.error(&err),
+  }
+}
+
 fn type_lambda_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
     state.return_stackframe(Val::lies());
@@ -713,81 +809,170 @@ fn apply_cb(args: Vec<Val>, state: &mut State) {
     }
   }
 
+  // the arguments are values already, so call straight away in this frame
   state.replace_stackframe(list);
+  let frame = state.get_stackframe();
+  frame.pc = frame.accum.len();
 }
 
-fn format_cb(args: Vec<Val>, state: &mut State) {
-  let mut string = String::new();
-  for arg in args.iter() {
-    match arg {
-      Val::Num(num) => string.push_str(&num.to_string()),
-      Val::Sym(sym) => string.push_str(sym),
-      Val::String(sym) => string.push_str(sym),
-      _ => string.push_str(format!("{:?}", arg).as_str()),
-    }
+pub(crate) fn is_builtin(val: &Val, callback: Callback) -> bool {
+  match val {
+    Val::Builtin(_, cb) => *cb as usize == callback as usize,
+    _ => false,
   }
-  state.return_stackframe(Val::String(string));
 }
 
-fn set_program_cb(args: Vec<Val>, state: &mut State) {
-  println!("set_program_cb: {:?}", args);
+fn call_cc_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
     state.return_stackframe(Val::nil());
     return;
   }
 
-  state.set_main_program(args[0].clone());
-  state.return_stackframe(Val::nil());
+  let k = state.capture_continuation();
+  state.replace_stackframe(vec![args[0].clone(), k]);
+  let frame = state.get_stackframe();
+  frame.pc = frame.accum.len();
 }
 
-fn eval_cb(args: Vec<Val>, state: &mut State) {
+fn call_ec_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
     state.return_stackframe(Val::nil());
-  } else {
-    let mut list = vec![Val::Sym("do".to_string())];
-    list.extend(args);
-    state.replace_stackframe(list);
+    return;
+  }
+
+  state.add_escape_frame(args[0].clone());
+}
+
+/// Returns the value of `(f escape)` from a `call/ec` frame.
+pub(crate) fn escape_frame_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(args[args.len() - 1].clone());
+}
+
+/// Returns from the call that resumed a generator once the generator's
+/// lambda returns, leaving the generator done.
+pub(crate) fn generator_frame_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(Val::nil());
+  if let Val::Generator(id) = args[0] {
+    state.finish_generator(id);
   }
 }
 
-fn string_length_cb(args: Vec<Val>, state: &mut State) {
+/// `(unwind-protect body cleanup...)` evaluates the cleanup forms however
+/// `body` is left: by returning, by an error or by a continuation.
+fn unwind_protect_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
-    state.return_stackframe(Val::Num(0.0));
-  } else {
-    state.return_stackframe(Val::Num(read_string(&args[0]).len() as f32));
+    state.return_stackframe(Val::nil());
+    return;
   }
+
+  state.add_protect_frame(Val::nil(), args[0].clone(), args[1..].to_vec());
 }
 
-fn string_cons_cb(args: Vec<Val>, state: &mut State) {
-  let mut string = String::new();
-  for arg in args.iter() {
-    string.push_str(&read_string(arg));
+/// `(dynamic-wind before thunk after)` calls the three thunks in order, and
+/// `after` also when `thunk` is left early. Jumping back into `thunk` with
+/// a continuation calls `before` again.
+fn dynamic_wind_cb(args: Vec<Val>, state: &mut State) {
+  if args.len() < 3 {
+    state.return_stackframe(Val::nil());
+    return;
   }
-  state.return_stackframe(Val::String(string));
+
+  let body = Val::List(vec![
+    Val::Sym("do".to_string()),
+    Val::List(vec![args[0].clone()]),
+    Val::List(vec![args[1].clone()]),
+  ]);
+  state.add_protect_frame(args[0].clone(), body, vec![Val::List(vec![args[2].clone()])]);
+}
+
+/// Runs the cleanup of a protect frame whose body returned.
+pub(crate) fn protect_frame_cb(args: Vec<Val>, state: &mut State) {
+  let cleanup = match &args[2] {
+    Val::List(cleanup) => cleanup.clone(),
+    _ => vec![],
+  };
+  state.finish_protect_frame(cleanup, args[3].clone());
 }
 
-fn string_head_cb(args: Vec<Val>, state: &mut State) {
+pub(crate) fn return_first_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(args[0].clone());
+}
+
+/// Carries on with a non-local exit once a protect frame has cleaned up.
+pub(crate) fn unwind_resume_cb(args: Vec<Val>, state: &mut State) {
+  if let Val::List(transfer) = &args[0] {
+    state.replace_stackframe(transfer.clone());
+    let frame = state.get_stackframe();
+    frame.pc = frame.accum.len();
+  }
+}
+
+pub(crate) fn error_cb(args: Vec<Val>, state: &mut State) {
+  let message = args.iter().map(read_string).collect::<Vec<_>>().join(" ");
+  state.error(&message);
+}
+
+/// `(catch-cancel body handler)` evaluates `body`. If the program is
+/// cancelled meanwhile, `handler` is called with the reason instead, and
+/// its value is returned.
+fn catch_cancel_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
-    state.return_stackframe(Val::String("".to_string()));
-  } else {
-    let string = read_string(&args[0]);
-    if string.is_empty() {
-      state.return_stackframe(Val::String("".to_string()));
-    } else {
-      state.return_stackframe(Val::String(string[0..1].to_string()));
-    }
+    state.return_stackframe(Val::nil());
+    return;
   }
+
+  let handler = args.get(1).cloned().unwrap_or(Val::nil());
+  state.replace_stackframe(vec![
+    Val::Builtin(false, catch_frame_cb),
+    handler,
+    Val::nil(),
+  ]);
+  state.get_stackframe().pc = 2;
+  state.add_stackframe(vec![Val::Sym("do".to_string()), args[0].clone()]);
+}
+
+/// Returns the value of the body of a `catch-cancel` frame.
+pub(crate) fn catch_frame_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(args[args.len() - 1].clone());
 }
 
-fn string_tail_cb(args: Vec<Val>, state: &mut State) {
+pub(crate) fn cancel_cb(args: Vec<Val>, state: &mut State) {
+  let reason = args.first().map(read_string).unwrap_or_default();
+  state.cancel(&reason);
+}
+
+/// `(without-interrupts body...)` evaluates the body forms while keeping
+/// queued interrupts from starting.
+fn without_interrupts_cb(args: Vec<Val>, state: &mut State) {
+  state.replace_stackframe(vec![
+    Val::Builtin(false, without_interrupts_frame_cb),
+    Val::nil(),
+  ]);
+  state.get_stackframe().pc = 1;
+  let mut body = vec![Val::Sym("do".to_string())];
+  body.extend(args);
+  state.add_stackframe(body);
+}
+
+/// Returns the value of the body of a `without-interrupts` frame.
+pub(crate) fn without_interrupts_frame_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(args[0].clone());
+}
+
+fn current_interrupt_cb(_args: Vec<Val>, state: &mut State) {
+  let source = match state.current_interrupt() {
+    Some(source) => Val::Sym(source.to_string()),
+    None => Val::nil(),
+  };
+  state.return_stackframe(source);
+}
+
+fn eval_cb(args: Vec<Val>, state: &mut State) {
   if args.is_empty() {
-    state.return_stackframe(Val::String("".to_string()));
+    state.return_stackframe(Val::nil());
   } else {
-    let string = read_string(&args[0]);
-    if string.is_empty() {
-      state.return_stackframe(Val::String("".to_string()));
-    } else {
-      state.return_stackframe(Val::String(string[1..].to_string()));
-    }
+    let mut list = vec![Val::Sym("do".to_string())];
+    list.extend(args);
+    state.replace_stackframe(list);
   }
 }
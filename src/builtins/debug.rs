@@ -0,0 +1,20 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State, object::read_string};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("print".to_string(), Val::Builtin(false, print_cb));
+  builtins.insert("memory-usage".to_string(), Val::Builtin(false, memory_usage_cb));
+}
+
+/// Prints the arguments on one line, returning the last.
+fn print_cb(args: Vec<Val>, state: &mut State) {
+  let line = args.iter().map(read_string).collect::<Vec<_>>().join(" ");
+  println!("{}", line);
+  state.return_stackframe(args.last().cloned().unwrap_or(Val::nil()));
+}
+
+fn memory_usage_cb(_args: Vec<Val>, state: &mut State) {
+  let usage = state.memory_usage();
+  state.return_stackframe(Val::Num(usage as f32));
+}
@@ -0,0 +1,27 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State, object::read_string};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("on".to_string(), Val::Builtin(false, on_cb));
+  builtins.insert("off".to_string(), Val::Builtin(false, off_cb));
+}
+
+/// `(on 'event handler)` calls `handler` with the arguments of every
+/// `State::emit` of the event. Returns a subscription for `off`.
+fn on_cb(args: Vec<Val>, state: &mut State) {
+  if args.len() < 2 || !args[1].is_callable() {
+    state.return_stackframe(Val::nil());
+    return;
+  }
+
+  let subscription = state.subscribe(&read_string(&args[0]), args[1].clone());
+  state.return_stackframe(subscription);
+}
+
+/// `(off subscription)` removes the handler `on` subscribed, returning
+/// whether it was still subscribed.
+fn off_cb(args: Vec<Val>, state: &mut State) {
+  let removed = args.first().is_some_and(|subscription| state.unsubscribe(subscription));
+  state.return_stackframe(if removed { Val::truth() } else { Val::nil() });
+}
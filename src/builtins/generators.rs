@@ -0,0 +1,43 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("generator".to_string(), Val::Builtin(false, generator_cb));
+  builtins.insert("generator-done?".to_string(), Val::Builtin(false, generator_done_cb));
+  builtins.insert("generator-close".to_string(), Val::Builtin(false, generator_close_cb));
+  builtins.insert("yield".to_string(), Val::Builtin(false, yield_cb));
+}
+
+fn generator_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
+    return;
+  }
+
+  let generator = state.add_generator(args[0].clone());
+  state.return_stackframe(generator);
+}
+
+fn generator_done_cb(args: Vec<Val>, state: &mut State) {
+  let done = match args.first() {
+    Some(Val::Generator(id)) => state.generator_done(*id),
+    _ => true,
+  };
+  if done {
+    state.return_stackframe(Val::truth());
+  } else {
+    state.return_stackframe(Val::lies());
+  }
+}
+
+/// `(generator-close g)` drops a generator that won't be resumed again,
+/// which then counts as done. See `State::close_generator`.
+fn generator_close_cb(args: Vec<Val>, state: &mut State) {
+  let closed = args.first().is_some_and(|generator| state.close_generator(generator));
+  state.return_stackframe(if closed { Val::truth() } else { Val::nil() });
+}
+
+fn yield_cb(args: Vec<Val>, state: &mut State) {
+  state.yield_value(args.first().cloned().unwrap_or_default());
+}
@@ -0,0 +1,18 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("set-program".to_string(), Val::Builtin(false, set_program_cb));
+}
+
+fn set_program_cb(args: Vec<Val>, state: &mut State) {
+  println!("set_program_cb: {:?}", args);
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
+    return;
+  }
+
+  state.set_main_program(args[0].clone());
+  state.return_stackframe(Val::nil());
+}
@@ -0,0 +1,39 @@
+use std::collections::HashMap;
+
+use crate::{val::{Val, p_all}, exec::State};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("load".to_string(), Val::Builtin(false, load_cb));
+}
+
+fn load_cb(args: Vec<Val>, state: &mut State) {
+  println!("load_cb: {:?}", args);
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
+    return;
+  }
+
+  let filename = if let Val::String(filename) = &args[0] {
+    filename
+  } else {
+    println!("Could not load file: {:?}", args[0]);
+    state.return_stackframe(Val::nil());
+    return;
+  };
+
+  println!("Loading file: {}", filename);
+
+  match state.read_script(filename) {
+    Ok(file) => match p_all(&file) {
+      Ok(val) => {
+        println!("file loaded: {:?}", val);
+        state.replace_stackframe(val);
+      },
+      Err(error) => state.error(&format!("Could not parse {}: {}", filename, error)),
+    },
+    Err(err) => {
+      println!("Could not load file: {}", filename);
+      state.error(&err.to_string());
+    },
+  }
+}
@@ -0,0 +1,548 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State, object::{try_read_object, entry_key, write_entry}};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("car".to_string(), Val::Builtin(false, car_cb));
+  builtins.insert("cdr".to_string(), Val::Builtin(false, cdr_cb));
+  builtins.insert("cons".to_string(), Val::Builtin(false, cons_cb));
+  builtins.insert("length".to_string(), Val::Builtin(false, length_cb));
+  builtins.insert("map".to_string(), Val::Builtin(false, map_cb));
+  builtins.insert("for-each".to_string(), Val::Builtin(false, for_each_cb));
+  builtins.insert("filter".to_string(), Val::Builtin(false, filter_cb));
+  builtins.insert("fold".to_string(), Val::Builtin(false, fold_cb));
+  builtins.insert("sort".to_string(), Val::Builtin(false, sort_cb));
+  builtins.insert("append".to_string(), Val::Builtin(false, append_cb));
+  builtins.insert("reverse".to_string(), Val::Builtin(false, reverse_cb));
+  builtins.insert("nth".to_string(), Val::Builtin(false, nth_cb));
+  builtins.insert("member".to_string(), Val::Builtin(false, member_cb));
+  builtins.insert("assoc".to_string(), Val::Builtin(false, assoc_cb));
+  builtins.insert("range".to_string(), Val::Builtin(false, range_cb));
+  builtins.insert("obj-get".to_string(), Val::Builtin(false, obj_get_cb));
+  builtins.insert("obj-set".to_string(), Val::Builtin(false, obj_set_cb));
+  builtins.insert("obj-has?".to_string(), Val::Builtin(false, obj_has_cb));
+  builtins.insert("obj-keys".to_string(), Val::Builtin(false, obj_keys_cb));
+  builtins.insert("obj-merge".to_string(), Val::Builtin(false, obj_merge_cb));
+}
+
+fn car_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
+  } else if let Val::List(list) = &args[0] {
+    if list.is_empty() {
+      state.return_stackframe(Val::nil());
+    } else {
+      state.return_stackframe(list[0].clone());
+    }
+  } else {
+    state.return_stackframe(Val::nil());
+  }
+}
+
+fn cdr_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
+  } else if let Val::List(list) = &args[0] {
+    if list.is_empty() {
+      state.return_stackframe(Val::nil());
+    } else {
+      state.return_stackframe(Val::List(list[1..].to_vec()));
+    }
+  } else {
+    state.return_stackframe(Val::nil());
+  }
+}
+
+fn cons_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::nil());
+  } else if let Val::List(list) = &args[1] {
+    let mut new_list = vec![args[0].clone()];
+    new_list.extend(list.clone());
+    state.return_stackframe(Val::List(new_list));
+  } else {
+    state.return_stackframe(Val::nil());
+  }
+}
+
+/// The number of items in a list.
+fn length_cb(args: Vec<Val>, state: &mut State) {
+  match args.first() {
+    Some(Val::List(list)) => state.return_stackframe(Val::Num(list.len() as f32)),
+    _ => state.error("length expects a list"),
+  }
+}
+
+// A loop over a list runs in a frame of its own, laid out as
+// (loop f items acc item value (next)), with the items left in reverse so
+// the next one pops off the end. While `f` runs, the frame's pc rests on
+// the value slot, so `f`'s value lands there and `(next)` is evaluated
+// after it. The callback of that frame carries on with the loop frame
+// below it, changing its state in place, so a step takes constant time
+// however long the list is.
+const F: usize = 1;
+const ITEMS: usize = 2;
+const ACC: usize = 3;
+const ITEM: usize = 4;
+const VALUE: usize = 5;
+
+/// Takes the value of the last call of `f`, if there was one, and returns
+/// the arguments of the next call, or `None` when the loop is done and its
+/// value is in the acc slot.
+type Advance = fn(&mut [Val], Option<Val>) -> Option<Vec<Val>>;
+
+/// Turns the top frame into a loop frame and makes its first call.
+fn start_loop(state: &mut State, advance: Advance, next: Val, f: &Val, mut items: Vec<Val>, acc: Val) {
+  items.reverse();
+  let next = Val::List(vec![next]);
+  state.replace_stackframe(vec![Val::Builtin(false, loop_cb), f.clone(), Val::List(items), acc, Val::nil(), Val::nil(), next]);
+  let frame = state.get_stackframe();
+  match advance(&mut frame.accum, None) {
+    Some(args) => {
+      frame.pc = VALUE;
+      let mut call = vec![f.clone()];
+      call.extend(args);
+      state.add_stackframe(call);
+      let frame = state.get_stackframe();
+      frame.pc = frame.accum.len();
+    },
+    None => {
+      let acc = std::mem::take(&mut frame.accum[ACC]);
+      state.return_stackframe(acc);
+    },
+  }
+}
+
+/// Carries on with the loop frame under the top frame, which is the one
+/// `(next)` pushed. It becomes the next call of `f`, or is popped along
+/// with the loop frame once the loop is done.
+fn next_step(state: &mut State, advance: Advance) {
+  let below = state.stack.len() - 2;
+  let frame = &mut state.stack[below];
+  let value = std::mem::take(&mut frame.accum[VALUE]);
+  match advance(&mut frame.accum, Some(value)) {
+    Some(args) => {
+      frame.pc = VALUE;
+      let mut call = vec![frame.accum[F].clone()];
+      call.extend(args);
+      state.replace_stackframe(call);
+      let frame = state.get_stackframe();
+      frame.pc = frame.accum.len();
+    },
+    None => {
+      let acc = std::mem::take(&mut frame.accum[ACC]);
+      state.pop_stackframe(&acc);
+      state.return_stackframe(acc);
+    },
+  }
+}
+
+/// The head of a loop frame. Loops return from their `(next)` frames, so
+/// this is only here to give the frame a callable head.
+fn loop_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(args.get(ACC - 1).cloned().unwrap_or_default());
+}
+
+fn next_item(frame: &mut [Val]) -> Option<Val> {
+  match &mut frame[ITEMS] {
+    Val::List(items) => items.pop(),
+    _ => None,
+  }
+}
+
+fn push_acc(frame: &mut [Val], val: Val) {
+  if let Val::List(acc) = &mut frame[ACC] {
+    acc.push(val);
+  }
+}
+
+/// Reads `(f list)` arguments, where `f` is called on the items.
+fn read_fn_list<'a>(name: &str, args: &'a [Val]) -> Result<(&'a Val, &'a [Val]), String> {
+  match args {
+    [f, Val::List(list)] if f.is_callable() => Ok((f, list)),
+    _ => Err(format!("{} expects a function and a list", name)),
+  }
+}
+
+/// `(map f list)` is the list of `f` called on each item.
+fn map_cb(args: Vec<Val>, state: &mut State) {
+  match read_fn_list("map", &args) {
+    Ok((f, list)) => start_loop(state, map_advance, Val::Builtin(false, map_next_cb), f, list.to_vec(), Val::nil()),
+    Err(err) => state.error(&err),
+  }
+}
+
+fn map_next_cb(_: Vec<Val>, state: &mut State) {
+  next_step(state, map_advance);
+}
+
+fn map_advance(frame: &mut [Val], value: Option<Val>) -> Option<Vec<Val>> {
+  if let Some(value) = value {
+    push_acc(frame, value);
+  }
+  next_item(frame).map(|item| vec![item])
+}
+
+/// `(for-each f list)` calls `f` on each item for its effects, and
+/// returns nil.
+fn for_each_cb(args: Vec<Val>, state: &mut State) {
+  match read_fn_list("for-each", &args) {
+    Ok((f, list)) => start_loop(state, for_each_advance, Val::Builtin(false, for_each_next_cb), f, list.to_vec(), Val::nil()),
+    Err(err) => state.error(&err),
+  }
+}
+
+fn for_each_next_cb(_: Vec<Val>, state: &mut State) {
+  next_step(state, for_each_advance);
+}
+
+fn for_each_advance(frame: &mut [Val], _: Option<Val>) -> Option<Vec<Val>> {
+  next_item(frame).map(|item| vec![item])
+}
+
+/// `(filter f list)` is the items `f` returns non-nil for, in order.
+fn filter_cb(args: Vec<Val>, state: &mut State) {
+  match read_fn_list("filter", &args) {
+    Ok((f, list)) => start_loop(state, filter_advance, Val::Builtin(false, filter_next_cb), f, list.to_vec(), Val::nil()),
+    Err(err) => state.error(&err),
+  }
+}
+
+fn filter_next_cb(_: Vec<Val>, state: &mut State) {
+  next_step(state, filter_advance);
+}
+
+fn filter_advance(frame: &mut [Val], value: Option<Val>) -> Option<Vec<Val>> {
+  let item = std::mem::take(&mut frame[ITEM]);
+  if value.is_some_and(|value| !value.is_nil()) {
+    push_acc(frame, item);
+  }
+  let item = next_item(frame)?;
+  frame[ITEM] = item.clone();
+  Some(vec![item])
+}
+
+/// `(fold f init list)` calls `(f acc item)` on each item from the left,
+/// starting with `init` as `acc`, and returns the last `acc`.
+fn fold_cb(args: Vec<Val>, state: &mut State) {
+  match args.as_slice() {
+    [f, init, Val::List(list)] if f.is_callable() => {
+      start_loop(state, fold_advance, Val::Builtin(false, fold_next_cb), f, list.clone(), init.clone());
+    },
+    _ => state.error("fold expects a function, an initial value and a list"),
+  }
+}
+
+fn fold_next_cb(_: Vec<Val>, state: &mut State) {
+  next_step(state, fold_advance);
+}
+
+fn fold_advance(frame: &mut [Val], value: Option<Val>) -> Option<Vec<Val>> {
+  if let Some(value) = value {
+    frame[ACC] = value;
+  }
+  let item = next_item(frame)?;
+  Some(vec![std::mem::take(&mut frame[ACC]), item])
+}
+
+/// `(sort list less)` sorts by `(less a b)`, which is non-nil when `a`
+/// goes before `b`. Items neither goes before keep their order. It's a
+/// merge sort, so `less` is called O(n log n) times and the items are
+/// moved as often.
+fn sort_cb(args: Vec<Val>, state: &mut State) {
+  match args.as_slice() {
+    [Val::List(list), less] if less.is_callable() => {
+      let runs = list.iter().map(|item| Val::List(vec![item.clone()])).collect();
+      start_loop(state, sort_advance, Val::Builtin(false, sort_next_cb), less, runs, Val::nil());
+    },
+    _ => state.error("sort expects a list and a function"),
+  }
+}
+
+fn sort_next_cb(_: Vec<Val>, state: &mut State) {
+  next_step(state, sort_advance);
+}
+
+// The items of a sort's loop frame are the sorted runs left to merge in
+// this pass, and its acc the merged runs for the next pass. Its item is
+// the merge going on, (left right merged), with the runs being merged in
+// reverse so their heads pop off the end.
+fn sort_advance(frame: &mut [Val], value: Option<Val>) -> Option<Vec<Val>> {
+  let mut merge = match std::mem::take(&mut frame[ITEM]) {
+    Val::List(merge) => merge,
+    _ => vec![],
+  };
+  if let (Some(value), [Val::List(left), Val::List(right), Val::List(merged)]) = (value, merge.as_mut_slice()) {
+    let side = if value.is_nil() { left } else { right };
+    merged.extend(side.pop());
+  }
+  loop {
+    if let [Val::List(left), Val::List(right), Val::List(merged)] = merge.as_mut_slice() {
+      if let (Some(left_head), Some(right_head)) = (left.last(), right.last()) {
+        let args = vec![right_head.clone(), left_head.clone()];
+        frame[ITEM] = Val::List(merge);
+        return Some(args);
+      }
+      merged.extend(left.drain(..).rev());
+      merged.extend(right.drain(..).rev());
+      push_acc(frame, Val::List(std::mem::take(merged)));
+      merge.clear();
+    }
+
+    match (next_item(frame), next_item(frame)) {
+      (Some(Val::List(mut left)), Some(Val::List(mut right))) => {
+        left.reverse();
+        right.reverse();
+        merge = vec![Val::List(left), Val::List(right), Val::nil()];
+        continue;
+      },
+      (Some(run), _) => push_acc(frame, run),
+      (None, _) => {},
+    }
+
+    let Val::List(mut runs) = std::mem::take(&mut frame[ACC]) else { return None };
+    if runs.len() <= 1 {
+      frame[ACC] = runs.pop().unwrap_or_default();
+      return None;
+    }
+    runs.reverse();
+    frame[ITEMS] = Val::List(runs);
+    frame[ACC] = Val::nil();
+    merge = vec![];
+  }
+}
+
+fn append_cb(args: Vec<Val>, state: &mut State) {
+  let mut result = vec![];
+  for arg in args {
+    match arg {
+      Val::List(list) => result.extend(list),
+      _ => {
+        state.error("append expects lists");
+        return;
+      },
+    }
+  }
+  state.return_stackframe(Val::List(result));
+}
+
+fn reverse_cb(args: Vec<Val>, state: &mut State) {
+  match args.first() {
+    Some(Val::List(list)) => state.return_stackframe(Val::List(list.iter().rev().cloned().collect())),
+    _ => state.error("reverse expects a list"),
+  }
+}
+
+/// `(nth n list)` is the item at index `n`, or nil past the end.
+fn nth_cb(args: Vec<Val>, state: &mut State) {
+  match args.as_slice() {
+    [Val::Num(n), Val::List(list)] if *n >= 0.0 && n.fract() == 0.0 => {
+      state.return_stackframe(list.get(*n as usize).cloned().unwrap_or_default());
+    },
+    _ => state.error("nth expects an index and a list"),
+  }
+}
+
+/// `(member x list)` is the rest of the list from the first item equal to
+/// `x`, or nil if there is none.
+fn member_cb(args: Vec<Val>, state: &mut State) {
+  match args.as_slice() {
+    [x, Val::List(list)] => {
+      let rest = match list.iter().position(|item| item == x) {
+        Some(i) => Val::List(list[i..].to_vec()),
+        None => Val::nil(),
+      };
+      state.return_stackframe(rest);
+    },
+    _ => state.error("member expects a value and a list"),
+  }
+}
+
+/// `(assoc key alist)` is the first entry of `alist` whose car is `key`, or
+/// nil if there is none.
+fn assoc_cb(args: Vec<Val>, state: &mut State) {
+  match args.as_slice() {
+    [key, Val::List(alist)] => {
+      let entry = alist.iter().find(|entry| matches!(entry, Val::List(entry) if entry.first() == Some(key)));
+      state.return_stackframe(entry.cloned().unwrap_or_default());
+    },
+    _ => state.error("assoc expects a key and a list"),
+  }
+}
+
+// the longest list range makes, so a script can't use up the memory
+const MAX_RANGE: usize = 1_000_000;
+
+/// `(range end)`, `(range start end)` or `(range start end step)` counts
+/// from `start`, 0 by default, up to but not including `end`. Ranges of
+/// more than `MAX_RANGE` numbers are an error.
+fn range_cb(args: Vec<Val>, state: &mut State) {
+  let nums = args.iter().map(|arg| match arg {
+    Val::Num(num) => Some(*num),
+    _ => None,
+  }).collect::<Option<Vec<f32>>>();
+  let (start, end, step) = match nums.as_deref() {
+    Some(nums) if nums.iter().any(|num| !num.is_finite()) => {
+      state.error("range expects finite numbers");
+      return;
+    },
+    Some([end]) => (0.0, *end, 1.0),
+    Some([start, end]) => (*start, *end, 1.0),
+    Some([start, end, step]) if *step != 0.0 => (*start, *end, *step),
+    Some([_, _, _]) => {
+      state.error("range step can't be 0");
+      return;
+    },
+    _ => {
+      state.error("range expects 1 to 3 numbers");
+      return;
+    },
+  };
+  if (end - start) / step > MAX_RANGE as f32 {
+    state.error(&format!("range would be longer than {} numbers", MAX_RANGE));
+    return;
+  }
+
+  let mut list = vec![];
+  let mut i = 0;
+  loop {
+    let num = start + step * i as f32;
+    if (step > 0.0 && num >= end) || (step < 0.0 && num <= end) {
+      break;
+    }
+    list.push(Val::Num(num));
+    i += 1;
+  }
+  state.return_stackframe(Val::List(list));
+}
+
+/// Reads the key argument of the object builtins, a symbol or a keyword.
+fn read_key<'a>(name: &str, key: Option<&'a Val>) -> Result<&'a str, String> {
+  match key {
+    Some(Val::Sym(key)) | Some(Val::Keyword(key)) => Ok(key),
+    _ => Err(format!("{} expects a symbol key", name)),
+  }
+}
+
+/// The value of `key` as `read_object` reads it, from the last entry with
+/// that key, which is also what a host filling in fields from
+/// `read_object` ends up with.
+fn object_get(object: &Val, key: &str) -> Result<Option<Val>, String> {
+  let mut found = None;
+  try_read_object(object, |k, val| {
+    if k == key {
+      found = Some(val.clone());
+    }
+  })?;
+  Ok(found)
+}
+
+/// Sets the entries of `updates` in `object`. The first entry of a key
+/// already there is replaced in place and any others dropped, and new keys
+/// go at the end.
+fn object_update(object: &Val, updates: Vec<(String, Val)>) -> Result<Val, String> {
+  let Val::List(entries) = object else {
+    return Err("Invalid object".to_string());
+  };
+  let mut result: Vec<Val> = vec![];
+  let mut written = vec![false; updates.len()];
+  for entry in entries {
+    let update = match entry_key(entry)? {
+      Some(key) => updates.iter().rposition(|(k, _)| k == key),
+      None => None,
+    };
+    match update {
+      Some(i) if written[i] => {},
+      Some(i) => {
+        written[i] = true;
+        result.push(updates[i].1.clone());
+      },
+      None => result.push(entry.clone()),
+    }
+  }
+  for (i, (key, entry)) in updates.iter().enumerate() {
+    let later = updates[i + 1..].iter().any(|(k, _)| k == key);
+    if !written[i] && !later {
+      result.push(entry.clone());
+    }
+  }
+  Ok(Val::List(result))
+}
+
+/// `(obj-get object key)` is the value of `key`, or `default` if given and
+/// the object has no such entry. A bare `(key)` flag reads as nil.
+fn obj_get_cb(args: Vec<Val>, state: &mut State) {
+  let value = read_key("obj-get", args.get(1)).and_then(|key| object_get(&args[0], key));
+  match value {
+    Ok(Some(value)) => state.return_stackframe(value),
+    Ok(None) => state.return_stackframe(args.get(2).cloned().unwrap_or_default()),
+    Err(err) => state.error(&err),
+  }
+}
+
+/// `(obj-set object key value)` is a copy of the object with `key` set to
+/// `value`, written so `obj-get` and `read_object` read `value` back.
+fn obj_set_cb(args: Vec<Val>, state: &mut State) {
+  let updated = read_key("obj-set", args.get(1)).and_then(|key| {
+    let value = args.get(2).cloned().unwrap_or_default();
+    object_update(&args[0], vec![(key.to_string(), write_entry(key, &value))])
+  });
+  match updated {
+    Ok(object) => state.return_stackframe(object),
+    Err(err) => state.error(&err),
+  }
+}
+
+fn obj_has_cb(args: Vec<Val>, state: &mut State) {
+  let value = read_key("obj-has?", args.get(1)).and_then(|key| object_get(&args[0], key));
+  match value {
+    Ok(value) => state.return_stackframe(if value.is_some() { Val::truth() } else { Val::lies() }),
+    Err(err) => state.error(&err),
+  }
+}
+
+/// The keys of an object in the order they first appear.
+fn obj_keys_cb(args: Vec<Val>, state: &mut State) {
+  let mut keys: Vec<Val> = vec![];
+  let read = try_read_object(args.first().unwrap_or(&Val::nil()), |key, _| {
+    let key = Val::Sym(key.to_string());
+    if !keys.contains(&key) {
+      keys.push(key);
+    }
+  });
+  match read {
+    Ok(()) => state.return_stackframe(Val::List(keys)),
+    Err(err) => state.error(&err),
+  }
+}
+
+/// `(obj-merge object others...)` sets every entry of the others in the
+/// object, so later objects win.
+fn obj_merge_cb(args: Vec<Val>, state: &mut State) {
+  let Some((object, others)) = args.split_first() else {
+    state.return_stackframe(Val::nil());
+    return;
+  };
+
+  let mut updates = vec![];
+  for other in others {
+    let Val::List(entries) = other else {
+      state.error("Invalid object");
+      return;
+    };
+    for entry in entries {
+      match entry_key(entry) {
+        Ok(Some(key)) => updates.push((key.to_string(), entry.clone())),
+        Ok(None) => {},
+        Err(err) => {
+          state.error(&err);
+          return;
+        },
+      }
+    }
+  }
+  match object_update(object, updates) {
+    Ok(object) => state.return_stackframe(object),
+    Err(err) => state.error(&err),
+  }
+}
@@ -0,0 +1,397 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State, vec2::{Vec2, IVec2}};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("+".to_string(), Val::Builtin(false, plus_cb));
+  builtins.insert("-".to_string(), Val::Builtin(false, minus_cb));
+  builtins.insert("*".to_string(), Val::Builtin(false, mult_cb));
+  builtins.insert("/".to_string(), Val::Builtin(false, div_cb));
+  builtins.insert("%".to_string(), Val::Builtin(false, modulo_cb));
+  builtins.insert("<".to_string(), Val::Builtin(false, less_cb));
+  builtins.insert(">".to_string(), Val::Builtin(false, greater_cb));
+  builtins.insert("<=".to_string(), Val::Builtin(false, less_eq_cb));
+  builtins.insert(">=".to_string(), Val::Builtin(false, greater_eq_cb));
+  builtins.insert("sqrt".to_string(), Val::Builtin(false, sqrt_cb));
+  builtins.insert("expt".to_string(), Val::Builtin(false, expt_cb));
+  builtins.insert("sin".to_string(), Val::Builtin(false, sin_cb));
+  builtins.insert("cos".to_string(), Val::Builtin(false, cos_cb));
+  builtins.insert("atan2".to_string(), Val::Builtin(false, atan2_cb));
+  builtins.insert("floor".to_string(), Val::Builtin(false, floor_cb));
+  builtins.insert("ceiling".to_string(), Val::Builtin(false, ceiling_cb));
+  builtins.insert("round".to_string(), Val::Builtin(false, round_cb));
+  builtins.insert("truncate".to_string(), Val::Builtin(false, truncate_cb));
+  builtins.insert("abs".to_string(), Val::Builtin(false, abs_cb));
+  builtins.insert("min".to_string(), Val::Builtin(false, min_cb));
+  builtins.insert("max".to_string(), Val::Builtin(false, max_cb));
+  builtins.insert("clamp".to_string(), Val::Builtin(false, clamp_cb));
+  builtins.insert("lerp".to_string(), Val::Builtin(false, lerp_cb));
+  builtins.insert("random".to_string(), Val::Builtin(false, random_cb));
+  builtins.insert("random-int".to_string(), Val::Builtin(false, random_int_cb));
+  builtins.insert("random-choice".to_string(), Val::Builtin(false, random_choice_cb));
+  builtins.insert("shuffle".to_string(), Val::Builtin(false, shuffle_cb));
+  builtins.insert("vec2".to_string(), Val::Builtin(false, vec2_cb));
+  builtins.insert("ivec2".to_string(), Val::Builtin(false, ivec2_cb));
+  builtins.insert("vec2?".to_string(), Val::Builtin(false, is_vec2_cb));
+  builtins.insert("vec2-x".to_string(), Val::Builtin(false, vec2_x_cb));
+  builtins.insert("vec2-y".to_string(), Val::Builtin(false, vec2_y_cb));
+  builtins.insert("v+".to_string(), Val::Builtin(false, vec_plus_cb));
+  builtins.insert("v-".to_string(), Val::Builtin(false, vec_minus_cb));
+  builtins.insert("v*".to_string(), Val::Builtin(false, vec_mult_cb));
+  builtins.insert("dot".to_string(), Val::Builtin(false, dot_cb));
+  builtins.insert("normalize".to_string(), Val::Builtin(false, normalize_cb));
+  builtins.insert("distance".to_string(), Val::Builtin(false, distance_cb));
+  builtins.insert("manhattan".to_string(), Val::Builtin(false, manhattan_cb));
+  builtins.insert("v-length".to_string(), Val::Builtin(false, vec_length_cb));
+}
+
+/// Reads every argument as a number, failing with a type error naming the
+/// builtin for the first that isn't one.
+fn read_nums(name: &str, args: &[Val]) -> Result<Vec<f32>, String> {
+  args.iter().map(|arg| match arg {
+    Val::Num(num) => Ok(*num),
+    _ => Err(format!("{} expects numbers, got {}", name, arg.to_string())),
+  }).collect()
+}
+
+/// Reads exactly `count` number arguments.
+fn read_exact(name: &str, args: &[Val], count: usize) -> Result<Vec<f32>, String> {
+  if args.len() != count {
+    return Err(format!("{} expects {} arguments, got {}", name, count, args.len()));
+  }
+  read_nums(name, args)
+}
+
+fn return_num(state: &mut State, num: Result<f32, String>) {
+  match num {
+    Ok(num) => state.return_stackframe(Val::Num(num)),
+    Err(err) => state.error(&err),
+  }
+}
+
+/// Folds the arguments left to right with `op`. A single argument is
+/// passed to `unary`, and no arguments give `empty`.
+fn fold_nums(name: &str, args: &[Val], empty: f32, unary: fn(f32) -> f32, op: fn(f32, f32) -> f32) -> Result<f32, String> {
+  let nums = read_nums(name, args)?;
+  Ok(match nums.split_first() {
+    None => empty,
+    Some((first, [])) => unary(*first),
+    Some((first, rest)) => rest.iter().fold(*first, |acc, num| op(acc, *num)),
+  })
+}
+
+fn plus_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, fold_nums("+", &args, 0.0, |a| a, |a, b| a + b));
+}
+
+fn minus_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, fold_nums("-", &args, 0.0, |a| -a, |a, b| a - b));
+}
+
+fn mult_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, fold_nums("*", &args, 1.0, |a| a, |a, b| a * b));
+}
+
+fn div_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, fold_nums("/", &args, 0.0, |a| 1.0 / a, |a, b| a / b));
+}
+
+fn modulo_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, fold_nums("%", &args, 0.0, |a| a, |a, b| a % b));
+}
+
+/// Whether each argument is in order with the next by `cmp`. No arguments
+/// are never in order, and one always is.
+fn compare(name: &str, args: &[Val], state: &mut State, cmp: fn(f32, f32) -> bool) {
+  let nums = match read_nums(name, args) {
+    Ok(nums) => nums,
+    Err(err) => {
+      state.error(&err);
+      return;
+    },
+  };
+  let ordered = !nums.is_empty() && nums.windows(2).all(|pair| cmp(pair[0], pair[1]));
+  state.return_stackframe(if ordered { Val::truth() } else { Val::lies() });
+}
+
+fn greater_cb(args: Vec<Val>, state: &mut State) {
+  compare(">", &args, state, |a, b| a > b);
+}
+
+fn less_cb(args: Vec<Val>, state: &mut State) {
+  compare("<", &args, state, |a, b| a < b);
+}
+
+fn greater_eq_cb(args: Vec<Val>, state: &mut State) {
+  compare(">=", &args, state, |a, b| a >= b);
+}
+
+fn less_eq_cb(args: Vec<Val>, state: &mut State) {
+  compare("<=", &args, state, |a, b| a <= b);
+}
+
+fn unary(name: &str, args: &[Val], op: fn(f32) -> f32) -> Result<f32, String> {
+  let nums = read_exact(name, args, 1)?;
+  Ok(op(nums[0]))
+}
+
+fn sqrt_cb(args: Vec<Val>, state: &mut State) {
+  let root = read_exact("sqrt", &args, 1).and_then(|nums| {
+    if nums[0] < 0.0 {
+      Err(format!("sqrt of negative number {}", nums[0]))
+    } else {
+      Ok(nums[0].sqrt())
+    }
+  });
+  return_num(state, root);
+}
+
+/// `(expt base power)`
+fn expt_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_exact("expt", &args, 2).map(|nums| nums[0].powf(nums[1])));
+}
+
+fn sin_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, unary("sin", &args, f32::sin));
+}
+
+fn cos_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, unary("cos", &args, f32::cos));
+}
+
+/// `(atan2 y x)` is the angle of the point (x, y), in radians.
+fn atan2_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_exact("atan2", &args, 2).map(|nums| nums[0].atan2(nums[1])));
+}
+
+fn floor_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, unary("floor", &args, f32::floor));
+}
+
+fn ceiling_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, unary("ceiling", &args, f32::ceil));
+}
+
+/// Rounds halfway cases away from zero.
+fn round_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, unary("round", &args, f32::round));
+}
+
+fn truncate_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, unary("truncate", &args, f32::trunc));
+}
+
+fn abs_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, unary("abs", &args, f32::abs));
+}
+
+fn extreme(name: &str, args: &[Val], op: fn(f32, f32) -> f32) -> Result<f32, String> {
+  let nums = read_nums(name, args)?;
+  nums.into_iter().reduce(op).ok_or_else(|| format!("{} expects at least one number", name))
+}
+
+fn min_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, extreme("min", &args, f32::min));
+}
+
+fn max_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, extreme("max", &args, f32::max));
+}
+
+/// `(clamp x low high)`
+fn clamp_cb(args: Vec<Val>, state: &mut State) {
+  let clamped = read_exact("clamp", &args, 3).and_then(|nums| {
+    if nums[1] > nums[2] {
+      Err(format!("clamp bounds out of order: {} > {}", nums[1], nums[2]))
+    } else {
+      Ok(nums[0].clamp(nums[1], nums[2]))
+    }
+  });
+  return_num(state, clamped);
+}
+
+/// `(lerp a b t)` is `a` at t = 0 and `b` at t = 1.
+fn lerp_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_exact("lerp", &args, 3).map(|nums| nums[0] + (nums[1] - nums[0]) * nums[2]));
+}
+
+/// `(random)` is a number in [0, 1), and `(random n)` one in [0, n).
+fn random_cb(args: Vec<Val>, state: &mut State) {
+  let scale = match args.len() {
+    0 => Ok(1.0),
+    _ => read_exact("random", &args, 1).map(|nums| nums[0]),
+  };
+  let roll = state.rng.next_f32();
+  return_num(state, scale.map(|scale| roll * scale));
+}
+
+/// `(random-int n)` is an integer in [0, n), and `(random-int low high)`
+/// one in [low, high).
+fn random_int_cb(args: Vec<Val>, state: &mut State) {
+  let bounds = match args.len() {
+    1 => read_nums("random-int", &args).map(|nums| (0.0, nums[0])),
+    _ => read_exact("random-int", &args, 2).map(|nums| (nums[0], nums[1])),
+  };
+  let roll = bounds.and_then(|(low, high)| {
+    if low.fract() != 0.0 || high.fract() != 0.0 {
+      Err("random-int expects integer bounds".to_string())
+    } else if low >= high {
+      Err(format!("random-int has an empty range from {} to {}", low, high))
+    } else {
+      Ok(low + state.rng.below((high - low) as usize) as f32)
+    }
+  });
+  return_num(state, roll);
+}
+
+fn random_choice_cb(args: Vec<Val>, state: &mut State) {
+  match args.first() {
+    Some(Val::List(list)) if !list.is_empty() => {
+      let i = state.rng.below(list.len());
+      state.return_stackframe(list[i].clone());
+    },
+    Some(Val::List(_)) => state.error("random-choice of an empty list"),
+    _ => state.error("random-choice expects a list"),
+  }
+}
+
+/// Returns a shuffled copy of a list.
+fn shuffle_cb(args: Vec<Val>, state: &mut State) {
+  match args.first() {
+    Some(Val::List(list)) => {
+      let mut list = list.clone();
+      for i in (1..list.len()).rev() {
+        let j = state.rng.below(i + 1);
+        list.swap(i, j);
+      }
+      state.return_stackframe(Val::List(list));
+    },
+    _ => state.error("shuffle expects a list"),
+  }
+}
+
+fn return_val(state: &mut State, val: Result<Val, String>) {
+  match val {
+    Ok(val) => state.return_stackframe(val),
+    Err(err) => state.error(&err),
+  }
+}
+
+fn vec2_cb(args: Vec<Val>, state: &mut State) {
+  let v = read_exact("vec2", &args, 2).map(|nums| Val::Vec2(Vec2::new(nums[0], nums[1])));
+  return_val(state, v);
+}
+
+/// Makes a grid position, truncating float coordinates.
+fn ivec2_cb(args: Vec<Val>, state: &mut State) {
+  let v = read_exact("ivec2", &args, 2).map(|nums| Val::IVec2(IVec2::new(nums[0] as i32, nums[1] as i32)));
+  return_val(state, v);
+}
+
+fn is_vec2_cb(args: Vec<Val>, state: &mut State) {
+  let is_vec2 = matches!(args.first(), Some(Val::Vec2(_)) | Some(Val::IVec2(_)));
+  state.return_stackframe(if is_vec2 { Val::truth() } else { Val::lies() });
+}
+
+/// Reads every argument as a vec2.
+fn read_vecs(name: &str, args: &[Val]) -> Result<Vec<Vec2>, String> {
+  args.iter().map(|arg| match arg {
+    Val::Vec2(v) => Ok(*v),
+    Val::IVec2(v) => Ok((*v).into()),
+    _ => Err(format!("{} expects vec2s, got {}", name, arg.to_string())),
+  }).collect()
+}
+
+fn read_vecs_exact(name: &str, args: &[Val], count: usize) -> Result<Vec<Vec2>, String> {
+  if args.len() != count {
+    return Err(format!("{} expects {} arguments, got {}", name, count, args.len()));
+  }
+  read_vecs(name, args)
+}
+
+/// The arguments as grid positions, if they all are. Their arithmetic is
+/// done in integers, exact beyond what a float holds.
+fn read_ivecs(args: &[Val]) -> Option<Vec<IVec2>> {
+  args.iter().map(|arg| match arg {
+    Val::IVec2(v) => Some(*v),
+    _ => None,
+  }).collect()
+}
+
+fn vec2_x_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_vecs_exact("vec2-x", &args, 1).map(|vecs| vecs[0].x));
+}
+
+fn vec2_y_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_vecs_exact("vec2-y", &args, 1).map(|vecs| vecs[0].y));
+}
+
+fn vec_plus_cb(args: Vec<Val>, state: &mut State) {
+  if let Some(vecs) = read_ivecs(&args) {
+    state.return_stackframe(Val::IVec2(vecs.into_iter().fold(IVec2::default(), |acc, v| acc + v)));
+    return;
+  }
+  let sum = read_vecs("v+", &args).map(|vecs| {
+    Val::Vec2(vecs.into_iter().fold(Vec2::default(), |acc, v| acc + v))
+  });
+  return_val(state, sum);
+}
+
+/// Subtracts the rest from the first, or negates a single vec2.
+fn vec_minus_cb(args: Vec<Val>, state: &mut State) {
+  if let Some(vecs) = read_ivecs(&args) {
+    let v = match vecs.split_first() {
+      None => IVec2::default(),
+      Some((first, [])) => IVec2::default() - *first,
+      Some((first, rest)) => rest.iter().fold(*first, |acc, v| acc - *v),
+    };
+    state.return_stackframe(Val::IVec2(v));
+    return;
+  }
+  let difference = read_vecs("v-", &args).map(|vecs| {
+    let v = match vecs.split_first() {
+      None => Vec2::default(),
+      Some((first, [])) => *first * -1.0,
+      Some((first, rest)) => rest.iter().fold(*first, |acc, v| acc - *v),
+    };
+    Val::Vec2(v)
+  });
+  return_val(state, difference);
+}
+
+/// `(v* v scale)` scales a vec2. A grid position stays one when scaled by
+/// an integer.
+fn vec_mult_cb(args: Vec<Val>, state: &mut State) {
+  let product = match args.as_slice() {
+    [Val::IVec2(v), Val::Num(scale)] | [Val::Num(scale), Val::IVec2(v)] if scale.fract() == 0.0 => {
+      Ok(Val::IVec2(*v * *scale as i32))
+    },
+    [Val::Vec2(v), Val::Num(scale)] | [Val::Num(scale), Val::Vec2(v)] => Ok(Val::Vec2(*v * *scale)),
+    [Val::IVec2(v), Val::Num(scale)] | [Val::Num(scale), Val::IVec2(v)] => Ok(Val::Vec2(Vec2::from(*v) * *scale)),
+    _ => Err("v* expects a vec2 and a number".to_string()),
+  };
+  return_val(state, product);
+}
+
+fn dot_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_vecs_exact("dot", &args, 2).map(|vecs| vecs[0].dot(vecs[1])));
+}
+
+fn vec_length_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_vecs_exact("v-length", &args, 1).map(|vecs| vecs[0].length()));
+}
+
+/// The unit vec2 in the same direction. A zero vec2 stays zero.
+fn normalize_cb(args: Vec<Val>, state: &mut State) {
+  let unit = read_vecs_exact("normalize", &args, 1).map(|vecs| Val::Vec2(vecs[0].normalize()));
+  return_val(state, unit);
+}
+
+fn distance_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_vecs_exact("distance", &args, 2).map(|vecs| vecs[0].distance(vecs[1])));
+}
+
+/// The distance along grid lines.
+fn manhattan_cb(args: Vec<Val>, state: &mut State) {
+  return_num(state, read_vecs_exact("manhattan", &args, 2).map(|vecs| vecs[0].manhattan(vecs[1])));
+}
@@ -0,0 +1,55 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("module".to_string(), Val::Builtin(true, module_cb));
+  builtins.insert("require".to_string(), Val::Builtin(true, require_cb));
+}
+
+/// `(module name (provide exports...) body...)` evaluates the body in a
+/// scope of its own, from which `require` takes only the exports.
+fn module_cb(args: Vec<Val>, state: &mut State) {
+  let name = match args.first() {
+    Some(Val::Sym(name)) => name.clone(),
+    _ => {
+      state.error("module expects a name");
+      return;
+    },
+  };
+
+  let mut body = args[1..].to_vec();
+  let mut exports = vec![];
+  if let Some(Val::List(provide)) = body.first() {
+    if provide.first() == Some(&Val::Sym("provide".to_string())) {
+      exports = provide[1..].iter().map(|export| export.to_string()).collect();
+      body.remove(0);
+    }
+  }
+
+  state.add_module_frame(&name, exports, body);
+}
+
+pub(crate) fn module_frame_cb(args: Vec<Val>, state: &mut State) {
+  let name = args[0].to_string();
+  state.finish_module(&name);
+  state.return_stackframe(args[0].clone());
+}
+
+/// `(require name imports...)` makes the exports of a module available
+/// as `name/export`, and the listed imports also without the prefix.
+fn require_cb(args: Vec<Val>, state: &mut State) {
+  let name = match args.first() {
+    Some(Val::Sym(name)) => name.clone(),
+    _ => {
+      state.error("require expects a module name");
+      return;
+    },
+  };
+
+  state.require(&name, args[1..].to_vec());
+}
+
+pub(crate) fn require_frame_cb(args: Vec<Val>, state: &mut State) {
+  state.bind_exports(&args[0].to_string());
+}
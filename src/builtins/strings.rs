@@ -0,0 +1,411 @@
+use std::collections::HashMap;
+
+use crate::{val::{Val, p}, exec::State, format::format, object::{read_string, read_args}};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("format".to_string(), Val::Builtin(false, format_cb));
+  builtins.insert("string-length".to_string(), Val::Builtin(false, string_length_cb));
+  builtins.insert("string-cons".to_string(), Val::Builtin(false, string_cons_cb));
+  builtins.insert("string-head".to_string(), Val::Builtin(false, string_head_cb));
+  builtins.insert("string-tail".to_string(), Val::Builtin(false, string_tail_cb));
+  builtins.insert("substring".to_string(), Val::Builtin(false, substring_cb));
+  builtins.insert("string-index".to_string(), Val::Builtin(false, string_index_cb));
+  builtins.insert("string-split".to_string(), Val::Builtin(false, string_split_cb));
+  builtins.insert("string-join".to_string(), Val::Builtin(false, string_join_cb));
+  builtins.insert("string-upcase".to_string(), Val::Builtin(false, string_upcase_cb));
+  builtins.insert("string-downcase".to_string(), Val::Builtin(false, string_downcase_cb));
+  builtins.insert("string-trim".to_string(), Val::Builtin(false, string_trim_cb));
+  builtins.insert("string-replace".to_string(), Val::Builtin(false, string_replace_cb));
+  builtins.insert("string-prefix?".to_string(), Val::Builtin(false, string_prefix_cb));
+  builtins.insert("string-suffix?".to_string(), Val::Builtin(false, string_suffix_cb));
+  builtins.insert("string->list".to_string(), Val::Builtin(false, string_to_list_cb));
+  builtins.insert("string-ref".to_string(), Val::Builtin(false, string_ref_cb));
+  builtins.insert("string".to_string(), Val::Builtin(false, string_cb));
+  builtins.insert("list->string".to_string(), Val::Builtin(false, list_to_string_cb));
+  builtins.insert("char?".to_string(), Val::Builtin(false, type_char_cb));
+  builtins.insert("char->integer".to_string(), Val::Builtin(false, char_to_integer_cb));
+  builtins.insert("integer->char".to_string(), Val::Builtin(false, integer_to_char_cb));
+  builtins.insert("char-alphabetic?".to_string(), Val::Builtin(false, char_alphabetic_cb));
+  builtins.insert("char-numeric?".to_string(), Val::Builtin(false, char_numeric_cb));
+  builtins.insert("char-whitespace?".to_string(), Val::Builtin(false, char_whitespace_cb));
+  builtins.insert("char-upcase".to_string(), Val::Builtin(false, char_upcase_cb));
+  builtins.insert("char-downcase".to_string(), Val::Builtin(false, char_downcase_cb));
+  builtins.insert("number->string".to_string(), Val::Builtin(false, number_to_string_cb));
+  builtins.insert("string->number".to_string(), Val::Builtin(false, string_to_number_cb));
+  builtins.insert("symbol->string".to_string(), Val::Builtin(false, symbol_to_string_cb));
+  builtins.insert("string->symbol".to_string(), Val::Builtin(false, string_to_symbol_cb));
+  builtins.insert("read-from-string".to_string(), Val::Builtin(false, read_from_string_cb));
+}
+
+/// `(format control args...)` fills in the directives of the control
+/// string, as described at `format::format`. Without any directives, or
+/// without a control string, `format` joins its arguments as it did before
+/// it had directives, so `(format "hp: " 5)` is still "hp: 5".
+fn format_cb(args: Vec<Val>, state: &mut State) {
+  if let Some((Val::String(control), rest)) = args.split_first() {
+    if control.contains('~') {
+      match format(control, rest) {
+        Ok(string) => state.return_stackframe(Val::String(string)),
+        Err(err) => state.error(&err),
+      }
+      return;
+    }
+  }
+
+  let mut string = String::new();
+  for arg in args.iter() {
+    match arg {
+      Val::Num(num) => string.push_str(&num.to_string()),
+      Val::Sym(sym) => string.push_str(sym),
+      Val::String(sym) => string.push_str(sym),
+      Val::Char(c) => string.push(*c),
+      _ => string.push_str(format!("{:?}", arg).as_str()),
+    }
+  }
+  state.return_stackframe(Val::String(string));
+}
+
+fn string_length_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::Num(0.0));
+  } else {
+    state.return_stackframe(Val::Num(read_string(&args[0]).chars().count() as f32));
+  }
+}
+
+fn string_cons_cb(args: Vec<Val>, state: &mut State) {
+  let mut string = String::new();
+  for arg in args.iter() {
+    string.push_str(&read_string(arg));
+  }
+  state.return_stackframe(Val::String(string));
+}
+
+fn string_head_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::String("".to_string()));
+  } else {
+    let string = read_string(&args[0]);
+    let head = string.chars().take(1).collect();
+    state.return_stackframe(Val::String(head));
+  }
+}
+
+fn string_tail_cb(args: Vec<Val>, state: &mut State) {
+  if args.is_empty() {
+    state.return_stackframe(Val::String("".to_string()));
+  } else {
+    let string = read_string(&args[0]);
+    let tail = string.chars().skip(1).collect();
+    state.return_stackframe(Val::String(tail));
+  }
+}
+
+/// Reads a char index or count, treating anything but a number as `default`.
+fn read_index(arg: Option<&Val>, default: usize) -> usize {
+  match arg {
+    Some(Val::Num(num)) if *num > 0.0 => *num as usize,
+    Some(Val::Num(_)) => 0,
+    _ => default,
+  }
+}
+
+fn read_string_arg(args: &[Val], index: usize) -> String {
+  args.get(index).map(read_string).unwrap_or_default()
+}
+
+fn truth(value: bool) -> Val {
+  if value { Val::truth() } else { Val::nil() }
+}
+
+/// `(substring s start end)` takes the chars from `start` up to `end`, or
+/// to the end of `s` if there's no `end`.
+fn substring_cb(args: Vec<Val>, state: &mut State) {
+  let string = read_string_arg(&args, 0);
+  let start = read_index(args.get(1), 0);
+  let end = read_index(args.get(2), usize::MAX);
+  let substring = string.chars().skip(start).take(end.saturating_sub(start)).collect();
+  state.return_stackframe(Val::String(substring));
+}
+
+/// `(string-index s needle start)` finds the char index of `needle` in `s`,
+/// searching from `start` if given.
+fn string_index_cb(args: Vec<Val>, state: &mut State) {
+  let string = read_string_arg(&args, 0);
+  let needle = read_string_arg(&args, 1);
+  let start = read_index(args.get(2), 0);
+  let mut offsets = string.char_indices().map(|(offset, _)| offset).chain([string.len()]);
+  let Some(offset) = offsets.nth(start) else {
+    state.return_stackframe(Val::nil());
+    return;
+  };
+
+  match string[offset..].find(&needle) {
+    Some(found) => {
+      let index = start + string[offset..offset + found].chars().count();
+      state.return_stackframe(Val::Num(index as f32));
+    },
+    None => state.return_stackframe(Val::nil()),
+  }
+}
+
+/// `(string-split s sep)` splits `s` at each `sep`, or at runs of whitespace
+/// if there's no `sep`.
+fn string_split_cb(args: Vec<Val>, state: &mut State) {
+  let string = read_string_arg(&args, 0);
+  let parts: Vec<Val> = match args.get(1) {
+    Some(sep) if !read_string(sep).is_empty() => {
+      string.split(read_string(sep).as_str()).map(|part| Val::String(part.to_string())).collect()
+    },
+    _ => string.split_whitespace().map(|part| Val::String(part.to_string())).collect(),
+  };
+  state.return_stackframe(Val::List(parts));
+}
+
+/// `(string-join list sep)` joins the strings of `list`, with `sep` between.
+fn string_join_cb(args: Vec<Val>, state: &mut State) {
+  let parts = match args.first() {
+    Some(Val::List(list)) => list.iter().map(read_string).collect::<Vec<_>>(),
+    _ => vec![],
+  };
+  let sep = read_string_arg(&args, 1);
+  state.return_stackframe(Val::String(parts.join(&sep)));
+}
+
+fn string_upcase_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(Val::String(read_string_arg(&args, 0).to_uppercase()));
+}
+
+fn string_downcase_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(Val::String(read_string_arg(&args, 0).to_lowercase()));
+}
+
+fn string_trim_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(Val::String(read_string_arg(&args, 0).trim().to_string()));
+}
+
+/// `(string-replace s from to)` replaces every `from` in `s` with `to`.
+fn string_replace_cb(args: Vec<Val>, state: &mut State) {
+  let string = read_string_arg(&args, 0);
+  let from = read_string_arg(&args, 1);
+  if from.is_empty() {
+    state.return_stackframe(Val::String(string));
+    return;
+  }
+  let to = read_string_arg(&args, 2);
+  state.return_stackframe(Val::String(string.replace(&from, &to)));
+}
+
+fn string_prefix_cb(args: Vec<Val>, state: &mut State) {
+  let prefix = read_string_arg(&args, 0).starts_with(&read_string_arg(&args, 1));
+  state.return_stackframe(truth(prefix));
+}
+
+fn string_suffix_cb(args: Vec<Val>, state: &mut State) {
+  let suffix = read_string_arg(&args, 0).ends_with(&read_string_arg(&args, 1));
+  state.return_stackframe(truth(suffix));
+}
+
+fn string_to_list_cb(args: Vec<Val>, state: &mut State) {
+  let chars = read_string_arg(&args, 0).chars().map(Val::Char).collect();
+  state.return_stackframe(Val::List(chars));
+}
+
+/// `(string-ref s i)` is the char at index `i`, or nil past the end.
+fn string_ref_cb(args: Vec<Val>, state: &mut State) {
+  let index = read_index(args.get(1), 0);
+  match read_string_arg(&args, 0).chars().nth(index) {
+    Some(c) => state.return_stackframe(Val::Char(c)),
+    None => state.return_stackframe(Val::nil()),
+  }
+}
+
+/// `(string chars...)` makes a string of the chars, or of the text of any
+/// other values.
+fn string_cb(args: Vec<Val>, state: &mut State) {
+  let string = args.iter().map(read_string).collect();
+  state.return_stackframe(Val::String(string));
+}
+
+fn list_to_string_cb(args: Vec<Val>, state: &mut State) {
+  let string = match args.first() {
+    Some(Val::List(list)) => list.iter().map(read_string).collect(),
+    _ => String::new(),
+  };
+  state.return_stackframe(Val::String(string));
+}
+
+fn type_char_cb(args: Vec<Val>, state: &mut State) {
+  state.return_stackframe(truth(matches!(args.first(), Some(Val::Char(_)))));
+}
+
+fn char_to_integer_cb(args: Vec<Val>, state: &mut State) {
+  match args.first() {
+    Some(Val::Char(c)) => state.return_stackframe(Val::Num(*c as u32 as f32)),
+    _ => state.error("char->integer expects a char"),
+  }
+}
+
+fn integer_to_char_cb(args: Vec<Val>, state: &mut State) {
+  let c = match args.first() {
+    Some(Val::Num(num)) if *num >= 0.0 => char::from_u32(*num as u32),
+    _ => None,
+  };
+  match c {
+    Some(c) => state.return_stackframe(Val::Char(c)),
+    None => state.error("integer->char expects a Unicode scalar value"),
+  }
+}
+
+/// Tests the char argument of a `char-...?` builtin, which raises a type
+/// error on anything else, as `char-upcase` does.
+fn char_test(name: &str, args: &[Val], test: fn(char) -> bool, state: &mut State) {
+  match args.first() {
+    Some(Val::Char(c)) => state.return_stackframe(truth(test(*c))),
+    _ => state.error(&format!("{} expects a char", name)),
+  }
+}
+
+fn char_alphabetic_cb(args: Vec<Val>, state: &mut State) {
+  char_test("char-alphabetic?", &args, char::is_alphabetic, state);
+}
+
+fn char_numeric_cb(args: Vec<Val>, state: &mut State) {
+  char_test("char-numeric?", &args, char::is_numeric, state);
+}
+
+fn char_whitespace_cb(args: Vec<Val>, state: &mut State) {
+  char_test("char-whitespace?", &args, char::is_whitespace, state);
+}
+
+/// Maps a char to a single char, keeping it where the mapping would give
+/// several, as for 'ß'.
+fn map_char(args: &[Val], map: fn(&char) -> String) -> Option<Val> {
+  match args.first() {
+    Some(Val::Char(c)) => {
+      let mapped = map(c);
+      let mut mapped = mapped.chars();
+      match (mapped.next(), mapped.next()) {
+        (Some(mapped), None) => Some(Val::Char(mapped)),
+        _ => Some(Val::Char(*c)),
+      }
+    },
+    _ => None,
+  }
+}
+
+fn char_upcase_cb(args: Vec<Val>, state: &mut State) {
+  match map_char(&args, |c| c.to_uppercase().collect()) {
+    Some(c) => state.return_stackframe(c),
+    None => state.error("char-upcase expects a char"),
+  }
+}
+
+fn char_downcase_cb(args: Vec<Val>, state: &mut State) {
+  match map_char(&args, |c| c.to_lowercase().collect()) {
+    Some(c) => state.return_stackframe(c),
+    None => state.error("char-downcase expects a char"),
+  }
+}
+
+// more decimals than an f32 could mean, so a script can't ask for gigabytes
+const MAX_PRECISION: f32 = 100.0;
+
+fn read_radix(arg: Option<&Val>) -> Option<u32> {
+  match arg {
+    None => Some(10),
+    Some(Val::Num(radix)) if (2.0..=36.0).contains(radix) && radix.fract() == 0.0 => Some(*radix as u32),
+    _ => None,
+  }
+}
+
+/// `(number->string n radix precision)` writes `n` in `radix`, which only
+/// works for integers unless it is 10, and with `precision` decimals if
+/// given, up to `MAX_PRECISION`. Both can also be passed as `#:radix` and
+/// `#:precision`.
+fn number_to_string_cb(args: Vec<Val>, state: &mut State) {
+  let args = read_args(&args);
+  let num = match args.positional.first() {
+    Some(Val::Num(num)) => *num,
+    _ => {
+      state.error("number->string expects a number");
+      return;
+    },
+  };
+  let Some(radix) = read_radix(args.named("radix", 1)) else {
+    state.error("number->string expects a radix from 2 to 36");
+    return;
+  };
+
+  let string = match args.named("precision", 2) {
+    Some(Val::Num(precision)) if radix == 10 => format!("{:.*}", precision.clamp(0.0, MAX_PRECISION) as usize, num),
+    _ if radix == 10 => num.to_string(),
+    _ if num.fract() == 0.0 => integer_to_radix(num as i64, radix),
+    _ => {
+      state.error("number->string can only write integers in other radixes");
+      return;
+    },
+  };
+  state.return_stackframe(Val::String(string));
+}
+
+fn integer_to_radix(num: i64, radix: u32) -> String {
+  let mut digits = vec![];
+  let mut rest = num.unsigned_abs();
+  loop {
+    digits.push(char::from_digit((rest % radix as u64) as u32, radix).unwrap());
+    rest /= radix as u64;
+    if rest == 0 {
+      break;
+    }
+  }
+  if num < 0 {
+    digits.push('-');
+  }
+  digits.iter().rev().collect()
+}
+
+/// `(string->number s radix)` reads a number, or returns an error symbol if
+/// `s` isn't one. The radix can also be passed as `#:radix`.
+fn string_to_number_cb(args: Vec<Val>, state: &mut State) {
+  let args = read_args(&args);
+  let string = read_string_arg(&args.positional, 0);
+  let text = string.trim();
+  let num = match read_radix(args.named("radix", 1)) {
+    Some(10) => text.parse::<f32>().ok().filter(|num| num.is_finite()),
+    Some(radix) => i64::from_str_radix(text, radix).ok().map(|num| num as f32),
+    None => None,
+  };
+  match num {
+    Some(num) => state.return_stackframe(Val::Num(num)),
+    None => state.return_stackframe(Val::Sym(format!("Error: Not a number: {}", string))),
+  }
+}
+
+fn symbol_to_string_cb(args: Vec<Val>, state: &mut State) {
+  match args.first() {
+    Some(Val::Sym(sym)) => state.return_stackframe(Val::String(sym.clone())),
+    Some(Val::Keyword(key)) => state.return_stackframe(Val::String(key.clone())),
+    _ => state.error("symbol->string expects a symbol"),
+  }
+}
+
+fn string_to_symbol_cb(args: Vec<Val>, state: &mut State) {
+  match args.first() {
+    Some(Val::String(string)) => state.return_stackframe(Val::Sym(string.clone())),
+    Some(Val::Sym(sym)) => state.return_stackframe(Val::Sym(sym.clone())),
+    _ => state.error("string->symbol expects a string"),
+  }
+}
+
+/// `(read-from-string s)` parses the first form in `s` without evaluating
+/// it, or returns an error symbol if there isn't one.
+fn read_from_string_cb(args: Vec<Val>, state: &mut State) {
+  let string = read_string_arg(&args, 0);
+  match p(&string) {
+    Val::Sym(sym) if sym.starts_with("Error parsing") => {
+      state.return_stackframe(Val::Sym(format!("Error: Could not read: {}", string)));
+    },
+    val => state.return_stackframe(val),
+  }
+}
@@ -0,0 +1,47 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State};
+
+pub(super) fn register(builtins: &mut HashMap<String, Val>) {
+  builtins.insert("after".to_string(), Val::Builtin(false, after_cb));
+  builtins.insert("every".to_string(), Val::Builtin(false, every_cb));
+  builtins.insert("cancel-timer".to_string(), Val::Builtin(false, cancel_timer_cb));
+}
+
+/// Reads a finite number of ticks that isn't negative. Delays too long to
+/// count to are cut to the longest there is, which is never reached.
+fn read_ticks(name: &str, ticks: Option<&Val>) -> Result<usize, String> {
+  match ticks {
+    Some(Val::Num(ticks)) if ticks.is_finite() && *ticks >= 0.0 => Ok(*ticks as usize),
+    _ => Err(format!("{} expects a finite number of ticks that isn't negative", name)),
+  }
+}
+
+/// `(after ticks f)` calls `f` once `ticks` host ticks have passed.
+fn after_cb(args: Vec<Val>, state: &mut State) {
+  match (read_ticks("after", args.first()), args.get(1)) {
+    (Ok(ticks), Some(f)) if f.is_callable() => {
+      let timer = state.add_timer(ticks, None, f.clone());
+      state.return_stackframe(timer);
+    },
+    (Err(err), _) => state.error(&err),
+    _ => state.return_stackframe(Val::nil()),
+  }
+}
+
+/// `(every ticks f)` calls `f` every `ticks` host ticks.
+fn every_cb(args: Vec<Val>, state: &mut State) {
+  match (read_ticks("every", args.first()), args.get(1)) {
+    (Ok(ticks), Some(f)) if f.is_callable() => {
+      let timer = state.add_timer(ticks, Some(ticks), f.clone());
+      state.return_stackframe(timer);
+    },
+    (Err(err), _) => state.error(&err),
+    _ => state.return_stackframe(Val::nil()),
+  }
+}
+
+fn cancel_timer_cb(args: Vec<Val>, state: &mut State) {
+  let cancelled = args.first().is_some_and(|timer| state.cancel_timer(timer));
+  state.return_stackframe(if cancelled { Val::truth() } else { Val::nil() });
+}
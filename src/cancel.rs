@@ -0,0 +1,119 @@
+use crate::{val::Val, exec::State, builtins::{cancel_cb, catch_frame_cb, is_builtin}};
+
+/// How a call to `State::run_for` left the program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum RunOutcome {
+  /// The program returned this value.
+  Finished(Val),
+  /// The program is waiting on a message, as given by `message_peek`.
+  Waiting(Vec<Val>),
+  /// The step budget ran out before the program finished.
+  Paused,
+  /// The program was cancelled for this reason, and nothing caught it.
+  Cancelled(String),
+}
+
+/// A step limit for one program. `level` is the length of the back stack
+/// while the program runs, which tells it apart from its interrupts.
+#[derive(Clone, Debug)]
+pub(crate) struct Deadline {
+  pub level: usize,
+  pub steps: usize,
+}
+
+impl State {
+  /// Cancels the running program. Cleanups run as for an error, and a
+  /// `catch-cancel` form can catch the cancellation, which is otherwise
+  /// reported by `run_for` as `RunOutcome::Cancelled`. Returns whether
+  /// there was a program to cancel.
+  pub fn cancel(&mut self, reason: &str) -> bool {
+    if self.stack.is_empty() {
+      return false;
+    }
+
+    let target = self.stack.iter().rposition(|frame| {
+      frame.pc == 2 && is_builtin(&frame.accum[0], catch_frame_cb)
+    });
+    let transfer = vec![Val::Builtin(false, cancel_cb), Val::String(reason.to_string())];
+    let reason = Val::String(reason.to_string());
+
+    match target {
+      Some(target) => {
+        if self.unwind(target + 1, transfer, &reason, &[]) {
+          let handler = self.get_stackframe().accum[1].clone();
+          if handler.is_nil() {
+            self.return_stackframe(reason);
+          } else {
+            self.replace_stackframe(vec![handler, reason]);
+          }
+        }
+      },
+      None => {
+        let cancelled = Val::Sym(format!("Error: Cancelled: {}", read_reason(&reason)));
+        if self.unwind(0, transfer, &cancelled, &[]) {
+          self.result = cancelled;
+          self.cancelled = Some(read_reason(&reason));
+        }
+      },
+    }
+    true
+  }
+
+  /// Cancels the running program once it has taken `steps` more steps,
+  /// with the reason "Deadline exceeded". Steps taken by interrupts don't
+  /// count. The deadline is dropped when the program finishes, is
+  /// replaced, or is cancelled.
+  pub fn set_deadline(&mut self, steps: usize) {
+    let level = self.back_stack.len();
+    self.clear_deadline();
+    self.deadlines.push(Deadline { level, steps });
+  }
+
+  /// Drops the deadline of the running program, if it has one.
+  pub fn clear_deadline(&mut self) {
+    let level = self.back_stack.len();
+    self.deadlines.retain(|deadline| deadline.level < level);
+  }
+
+  /// Counts a step against the deadline of the running program.
+  pub(crate) fn check_deadline(&mut self) {
+    let level = self.back_stack.len();
+    let expired = match self.deadlines.last_mut() {
+      Some(deadline) if deadline.level == level => {
+        deadline.steps = deadline.steps.saturating_sub(1);
+        deadline.steps == 0
+      },
+      _ => false,
+    };
+    if expired {
+      self.deadlines.pop();
+      self.cancel("Deadline exceeded");
+    }
+  }
+
+  /// Runs the program for at most `steps` steps.
+  pub fn run_for(&mut self, steps: usize) -> RunOutcome {
+    for _ in 0..steps {
+      if let Some(message) = self.message_peek() {
+        return RunOutcome::Waiting(message);
+      }
+      if let Some(result) = self.step() {
+        return match self.cancelled.take() {
+          Some(reason) => RunOutcome::Cancelled(reason),
+          None => RunOutcome::Finished(result),
+        };
+      }
+    }
+    match self.message_peek() {
+      Some(message) => RunOutcome::Waiting(message),
+      None => RunOutcome::Paused,
+    }
+  }
+}
+
+fn read_reason(reason: &Val) -> String {
+  match reason {
+    Val::String(reason) => reason.clone(),
+    _ => reason.to_string(),
+  }
+}
@@ -0,0 +1,61 @@
+use crate::{val::Val, exec::State};
+
+impl State {
+  /// Subscribes `handler` to `event`. Handlers are called in the order
+  /// they subscribed. Returns a handle for `unsubscribe`.
+  pub fn subscribe(&mut self, event: &str, handler: Val) -> Val {
+    // the handler is called long after the frame that subscribed it returns
+    if let Val::Lambda(_, scope, _) = &handler {
+      self.vars.pin(*scope);
+    }
+    self.marker_count += 1;
+    self.events.entry(event.to_string()).or_default().push((self.marker_count, handler));
+    Val::Subscription(self.marker_count)
+  }
+
+  /// Removes the handler a `subscribe` added, returning whether it was
+  /// still subscribed. Handles are unique, so a closure subscribed twice,
+  /// or another closure with the same code, stays subscribed.
+  pub fn unsubscribe(&mut self, subscription: &Val) -> bool {
+    let Val::Subscription(id) = subscription else {
+      return false;
+    };
+    let mut removed = None;
+    for handlers in self.events.values_mut() {
+      if let Some(i) = handlers.iter().position(|(handler_id, _)| handler_id == id) {
+        removed = Some(handlers.remove(i).1);
+        break;
+      }
+    }
+    match removed {
+      Some(handler) => {
+        if let Val::Lambda(_, scope, _) = handler {
+          self.unpin_scope(scope);
+        }
+        true
+      },
+      None => false,
+    }
+  }
+
+  /// The handlers subscribed to `event`.
+  pub fn handlers(&self, event: &str) -> impl Iterator<Item = &Val> {
+    self.events.get(event).into_iter().flatten().map(|(_, handler)| handler)
+  }
+
+  /// Calls every handler of `event` with `args`. Each call is queued as an
+  /// interrupt from the source named after the event, so handlers of one
+  /// event run one after another, in the order events were emitted and
+  /// handlers subscribed. Returns how many handlers were queued.
+  pub fn emit(&mut self, event: &str, args: Vec<Val>) -> usize {
+    let handlers: Vec<Val> = self.handlers(event).cloned().collect();
+    for handler in handlers.iter() {
+      let mut call = vec![handler.clone()];
+      call.extend(args.iter().map(|arg| {
+        Val::List(vec![Val::Sym("quote".to_string()), arg.clone()])
+      }));
+      self.queue_interrupt(event, Val::List(call));
+    }
+    handlers.len()
+  }
+}
@@ -1,6 +1,6 @@
-use std::{fmt::{Formatter, Debug}};
+use std::{collections::{HashMap, HashSet}, fmt::{Formatter, Debug}, sync::Arc};
 
-use crate::{val::{Val, p_all}, builtins::{get_builtins, do_cb}, variables::{VarSpace, ScopeRef}};
+use crate::{val::{Val, p_all}, builtins::{BuiltinGroup, get_builtin_group, do_cb, error_cb, escape_frame_cb, is_builtin}, unwind::protect_id, variables::{VarSpace, ScopeRef}, generator::{Generator, generator_id}, cancel::Deadline, interrupt::Interrupts, timers::TimerWheel, modules::Module, loader::{ScriptLoader, FsLoader, DenyLoader, LoadError}, profile::Profile, random::Rng, object::{read_args, read_string, Args}};
 
 #[derive(Clone)]
 pub struct Stackframe {
@@ -8,10 +8,13 @@ pub struct Stackframe {
   pub init: Vec<Val>,
   pub accum: Vec<Val>,
   pub pc: usize,
+  /// Scopes of earlier calls this frame replaced by tail calls, kept alive
+  /// because closures handed to the callee still refer to them.
+  pub retained: Vec<ScopeRef>,
 }
 
 impl Stackframe {
-  fn memory_usage(&self) -> usize {
+  pub(crate) fn memory_usage(&self) -> usize {
     let mut size = 4;
     for val in &self.accum {
       size += val.memory_usage();
@@ -32,18 +35,69 @@ pub struct State {
   pub stack: Vec<Stackframe>,
   pub back_stack: Vec<Vec<Stackframe>>,
   pub result: Val,
+  pub generators: HashMap<usize, Generator>,
+  pub(crate) marker_count: usize,
+  pub(crate) deadlines: Vec<Deadline>,
+  pub(crate) cancelled: Option<String>,
+  pub(crate) interrupts: Interrupts,
+  pub(crate) events: HashMap<String, Vec<(usize, Val)>>,
+  pub(crate) timers: TimerWheel,
+  pub(crate) modules: HashMap<String, Module>,
+  pub(crate) module_paths: Vec<String>,
+  pub(crate) loader: Arc<dyn ScriptLoader>,
+  pub(crate) profile: Profile,
+  pub(crate) denied: HashSet<String>,
+  pub(crate) rng: Rng,
+  pub(crate) records: HashMap<String, Vec<String>>,
 }
 
 impl State {
   pub fn new() -> State {
+    State::with_profile(Profile::trusted())
+  }
+
+  /// Creates a state whose scripts can only use what `profile` allows.
+  /// Without the `Io` group, scripts can't read anything through the
+  /// loader either, until the host sets one.
+  pub fn with_profile(profile: Profile) -> State {
     let mut vars = VarSpace::new();
     let root = vars.root();
-    vars.set_all(root, get_builtins());
+    let mut denied = HashSet::new();
+    for group in BuiltinGroup::ALL {
+      let builtins = get_builtin_group(group);
+      if !group.is_enabled() {
+        denied.extend(group.names().iter().map(|name| name.to_string()));
+      } else if profile.groups.contains(&group) {
+        vars.set_all(root, builtins);
+      } else {
+        denied.extend(builtins.into_keys());
+      }
+    }
+    let loader: Arc<dyn ScriptLoader> = if profile.groups.contains(&BuiltinGroup::Io) {
+      Arc::new(FsLoader::new("."))
+    } else {
+      Arc::new(DenyLoader)
+    };
+
     State { 
       vars,
       result: Val::nil(),
       stack: vec![],
       back_stack: vec![],
+      generators: HashMap::new(),
+      marker_count: 0,
+      deadlines: vec![],
+      cancelled: None,
+      interrupts: Interrupts::default(),
+      events: HashMap::new(),
+      timers: TimerWheel::default(),
+      modules: HashMap::new(),
+      module_paths: vec![],
+      loader,
+      profile,
+      denied,
+      rng: Rng::default(),
+      records: HashMap::new(),
     }
   }
 
@@ -59,17 +113,39 @@ impl State {
     s
   }
 
+  /// Runs the bundled standard library.
   pub fn load_lib(&mut self) {
+    self.run_lib(&String::from_utf8_lossy(include_bytes!("../cnvr/lib.cnvr")))
+      .expect("the bundled library parses");
+  }
+
+  /// Runs a standard library read by the loader from `path` instead of
+  /// the bundled one.
+  pub fn load_lib_from(&mut self, path: &str) -> Result<(), LoadError> {
+    let lib = self.read_script(path)?;
+    self.run_lib(&lib).map_err(|error| LoadError::Invalid(format!("{}: {}", path, error)))
+  }
+
+  fn run_lib(&mut self, lib: &str) -> Result<(), String> {
     println!("Loading library...");
-    //eval_s(&p("(load \"cnvr/lib.cnvr\")"), self);
-    let lib = include_bytes!("../cnvr/lib.cnvr");
-    let val = p_all(&String::from_utf8_lossy(lib));
+    let val = p_all(lib)?;
     self.add_stackframe(val);
     for _ in 0..10000 {
       if let Some(_) = self.step() {
         break;
       }
     }
+    Ok(())
+  }
+
+  /// Sets where `load`, `require` and `load_lib_from` read scripts from. Use
+  /// `DenyLoader` to keep scripts from reading anything.
+  pub fn set_loader(&mut self, loader: impl ScriptLoader + 'static) {
+    self.loader = Arc::new(loader);
+  }
+
+  pub fn read_script(&self, path: &str) -> Result<String, LoadError> {
+    self.loader.read(path)
   }
 
   pub fn get_var_ref(&self) -> ScopeRef {
@@ -106,21 +182,89 @@ impl State {
       init: list.clone(),
       accum: list,
       pc: 0,
+      retained: vec![],
     });
   }
 
-  pub fn return_stackframe(&mut self, val: Val) {
-    if !self.stack.is_empty() {
-      let scope = self.get_var_ref();
-      self.stack.pop();
+  pub(crate) fn pop_stackframe(&mut self, val: &Val) {
+    let scopes = self.take_owned_scopes();
+    self.stack.pop();
+    self.release_scopes(scopes, val);
+  }
 
-      if scope != self.get_var_ref() {
-        // try to clean up scope
-        if !self.vars.val_has_ancestor(scope, &val) {
-          self.vars.remove(scope);
-        }
+  /// Takes the scopes only the top frame uses: the one it entered, if it
+  /// doesn't share the scope of the frame below, and any it retained.
+  fn take_owned_scopes(&mut self) -> Vec<ScopeRef> {
+    let below = if self.stack.len() >= 2 {
+      self.stack[self.stack.len() - 2].vars
+    } else {
+      self.vars.root()
+    };
+    let frame = self.stack.last_mut().unwrap();
+    let mut scopes = std::mem::take(&mut frame.retained);
+    if frame.vars != below {
+      scopes.push(frame.vars);
+    }
+    scopes
+  }
+
+  /// Removes the scopes `val` doesn't refer to, returning the rest.
+  fn release_scopes(&mut self, scopes: Vec<ScopeRef>, val: &Val) -> Vec<ScopeRef> {
+    let mut kept = vec![];
+    for scope in scopes {
+      if self.vars.val_has_ancestor(scope, val) {
+        kept.push(scope);
+      } else {
+        self.vars.remove(scope);
       }
     }
+    kept
+  }
+
+  /// Releases a pin on `scope` taken for a closure the state held on to,
+  /// freeing the scopes nothing uses any more.
+  pub(crate) fn unpin_scope(&mut self, scope: ScopeRef) {
+    self.vars.unpin(scope);
+    self.collect_unpinned();
+  }
+
+  /// Frees unpinned scopes that nothing refers to. The ones still in use,
+  /// say by a timer callback waiting to run, are tried again later.
+  pub(crate) fn collect_unpinned(&mut self) {
+    let mut kept = vec![];
+    for scope in std::mem::take(&mut self.vars.unpinned) {
+      if self.vars.is_free(scope) || self.vars.is_pinned(scope) {
+        continue;
+      }
+      if self.scope_in_use(scope) {
+        kept.push(scope);
+      } else {
+        self.vars.remove(scope);
+      }
+    }
+    self.vars.unpinned.extend(kept);
+  }
+
+  /// Whether anything the state holds on to may still refer to `scope`.
+  fn scope_in_use(&self, scope: ScopeRef) -> bool {
+    let frames = self.stack.iter()
+      .chain(self.back_stack.iter().flatten())
+      .chain(self.generators.values().flat_map(|generator| generator.stack.iter()));
+    let vals = std::iter::once(&self.result)
+      .chain(self.generators.values().map(|generator| &generator.f))
+      .chain(self.events.values().flatten().map(|(_, handler)| handler))
+      .chain(self.timers.callbacks())
+      .chain(self.interrupts.pending.iter().map(|pending| &pending.program));
+
+    frames.collect::<Vec<_>>().into_iter().any(|frame| self.vars.frame_has_ancestor(scope, frame)) ||
+      vals.collect::<Vec<_>>().into_iter().any(|val| self.vars.val_has_ancestor(scope, val)) ||
+      self.vars.vars_have_ancestor(scope)
+  }
+
+  pub fn return_stackframe(&mut self, val: Val) {
+    if !self.stack.is_empty() {
+      self.pop_stackframe(&val);
+    }
     if self.stack.is_empty() {
       self.result = val;
     } else {
@@ -152,6 +296,15 @@ impl State {
       if let Val::Builtin(_, callback) = callable {
         let args = frame.accum[1..].to_vec();
         callback(args, self);
+      } else if let Val::Continuation(id, stack) = callable {
+        let val = frame.accum.get(1).cloned().unwrap_or_default();
+        self.resume_continuation(id, stack, val);
+      } else if let Val::Escape(id) = callable {
+        let val = frame.accum.get(1).cloned().unwrap_or_default();
+        self.escape(id, val);
+      } else if let Val::Generator(id) = callable {
+        let val = frame.accum.get(1).cloned().unwrap_or_default();
+        self.resume_generator(id, val);
       } else {
         let vars = match callable {
           Val::Lambda(_, vars, _) => vars,
@@ -185,38 +338,40 @@ impl State {
           self.return_stackframe(result);
         } else {
 
-          let var_ref = self.vars.new_child(vars);
-          let param_names = list[1].clone();
-          match param_names {
-            Val::List(param_names) => {
-              for (i, param_name) in param_names.iter().enumerate() {
-                if let Val::Sym(sym) = param_name {
-                  if i < params.len() {
-                    self.vars.set(var_ref, &sym.to_string(), params[i].clone());
-                  }
-                }
-              }
-            },
+          // this frame is replaced by the lambda body, which makes every call
+          // in tail position reuse its stackframe. Scopes of the replaced call
+          // go as well unless the callee or its arguments refer to them, so
+          // tail recursion runs in constant space.
+          let mut called = vec![frame.accum[0].clone()];
+          called.extend(params.iter().cloned());
+          let scopes = self.take_owned_scopes();
+          let retained = self.release_scopes(scopes, &Val::List(called));
 
-            Val::Sym(sym) => {
-              self.vars.set(var_ref, &sym.to_string(), Val::List(params));
+          let var_ref = self.vars.new_child(vars);
+          let frame = self.get_stackframe();
+          frame.vars = var_ref;
+          frame.retained = retained;
+
+          let inits = match self.bind_params(var_ref, &list[1], params) {
+            Ok(inits) => inits,
+            Err(message) => {
+              self.error(&message);
+              return;
             },
+          };
 
-            _ => {},
-          }
-
-          if list.len() > 3 {
+          if list.len() > 3 || !inits.is_empty() {
             let frame = self.get_stackframe();
             frame.accum = vec![Val::Sym("do".to_string())];
+            frame.accum.extend(inits);
             frame.accum.extend(list[2..].iter().cloned());
             frame.init = frame.accum.clone();
-            frame.vars = var_ref;
             frame.pc = 0;
 
           } else {
             match &list[2] {
               Val::List(list) => {
-                frame.vars = var_ref;
+                let frame = self.get_stackframe();
                 frame.init = list.clone();
                 frame.accum = list.clone();
                 frame.pc = 0;
@@ -313,15 +468,218 @@ impl State {
     }
   }
 
+  /// Binds call arguments to a lambda list in `scope`. Besides plain
+  /// parameters and a bare symbol collecting every argument, the list may
+  /// contain `#:optional` and `#:key` parameters, written either as a symbol
+  /// (defaulting to nil) or as `(name default)`, and a dotted rest parameter,
+  /// as in `(a #:optional (b 10) #:key (c 1) . rest)`. Keyword parameters are
+  /// passed as `#:c 2` anywhere in the call. Returns `define` forms for the
+  /// defaults that still have to be evaluated in the new scope, or an error
+  /// message when the arguments don't fit.
+  fn bind_params(&mut self, scope: ScopeRef, param_names: &Val, params: Vec<Val>) -> Result<Vec<Val>, String> {
+    let param_names = match param_names {
+      Val::List(param_names) => param_names,
+      Val::Sym(sym) => {
+        self.vars.set(scope, sym, Val::List(params));
+        return Ok(vec![]);
+      },
+      _ => return Ok(vec![]),
+    };
+
+    let keyword_marker = Val::Keyword("key".to_string());
+    let all_params = params.clone();
+    let keyword_mode = param_names.contains(&keyword_marker);
+    let (params, mut keywords) = if keyword_mode {
+      let args = read_args(&params);
+      (args.positional, args.keywords)
+    } else {
+      (params, vec![])
+    };
+    let mut has_rest = false;
+
+    let mut inits = vec![];
+    let mut section = "required";
+    let mut required = 0;
+    let mut i = 0;
+    let mut names = param_names.iter();
+    while let Some(param_name) = names.next() {
+      let (name, default) = match param_name {
+        Val::Keyword(key) if key == "optional" || key == "key" => {
+          section = key;
+          continue;
+        },
+
+        Val::Sym(sym) if sym == "." => {
+          if let Some(Val::Sym(rest)) = names.next() {
+            let rest_args = if keyword_mode {
+              rest_args(&all_params, i)
+            } else {
+              params.get(i..).unwrap_or_default().to_vec()
+            };
+            self.vars.set(scope, rest, Val::List(rest_args));
+            has_rest = true;
+          }
+          break;
+        },
+
+        Val::Sym(sym) => (sym, None),
+
+        Val::List(spec) if section != "required" && !spec.is_empty() => {
+          match &spec[0] {
+            Val::Sym(sym) => (sym, spec.get(1)),
+            _ => continue,
+          }
+        },
+
+        _ => continue,
+      };
+
+      let val = if section == "key" {
+        let pos = keywords.iter().position(|(key, _)| key == name);
+        pos.map(|pos| keywords.remove(pos).1)
+      } else {
+        i += 1;
+        params.get(i - 1).cloned()
+      };
+
+      match val {
+        Some(val) => {
+          self.vars.set(scope, name, val);
+        },
+
+        None => {
+          if section == "required" {
+            required += 1;
+            continue;
+          }
+          self.vars.set(scope, name, Val::nil());
+          if let Some(default) = default {
+            inits.push(Val::List(vec![
+              Val::Sym("define".to_string()),
+              Val::Sym(name.to_string()),
+              default.clone(),
+            ]));
+          }
+        },
+      }
+    }
+
+    if required > 0 {
+      return Err(format!("Too few arguments: expected {}, got {}", params.len() + required, params.len()));
+    }
+
+    // keywords no parameter takes are still in the rest list
+    if let Some((key, _)) = keywords.first().filter(|_| !has_rest) {
+      return Err(format!("Unknown keyword argument: #:{}", key));
+    }
+
+    Ok(inits)
+  }
+
+  /// Aborts the running program, leaving an error symbol as the result.
+  pub fn error(&mut self, message: &str) {
+    let error = Val::Sym(format!("Error: {}", message));
+    let transfer = vec![Val::Builtin(false, error_cb), Val::String(message.to_string())];
+    if self.unwind(0, transfer, &error, &[]) {
+      self.result = error;
+    }
+  }
+
+  /// Captures the current stack as a continuation. The scopes of its
+  /// frames stay alive for as long as the continuation is reachable from
+  /// a value a returning frame keeps, like the scopes of a closure.
+  pub fn capture_continuation(&mut self) -> Val {
+    self.marker_count += 1;
+    Val::Continuation(self.marker_count, self.stack.clone())
+  }
+
+  /// Abandons the current stack for a captured one. The top frame of a
+  /// captured stack is the `call/cc` that made it, which now returns `val`.
+  /// Cleanups run for protected frames left behind, and `dynamic-wind`
+  /// before thunks for the ones jumped back into.
+  pub fn resume_continuation(&mut self, id: usize, stack: Vec<Stackframe>, val: Val) {
+    let frame_id = |frame: &Stackframe| protect_id(frame).or_else(|| generator_id(frame));
+    let target_ids: Vec<usize> = stack.iter().filter_map(frame_id).collect();
+    let shared: Vec<usize> = self.stack.iter()
+      .filter_map(frame_id)
+      .filter(|id| target_ids.contains(id))
+      .collect();
+    let transfer = vec![Val::Continuation(id, stack.clone()), val.clone()];
+    if !self.unwind(0, transfer, &val, &shared) {
+      return;
+    }
+
+    let befores: Vec<Val> = stack.iter()
+      .filter(|frame| protect_id(frame).is_some_and(|id| !shared.contains(&id)))
+      .filter(|frame| !frame.accum[2].is_nil())
+      .map(|frame| Val::List(vec![frame.accum[2].clone()]))
+      .collect();
+
+    self.stack = stack;
+    if befores.is_empty() {
+      self.return_stackframe(val);
+    } else {
+      self.finish_protect_frame(befores, val);
+    }
+  }
+
+  /// Marks the top frame as the target of a new escape and returns it.
+  /// The frame evaluates `f` with the escape, and returns whatever `f`
+  /// returns unless the escape is used first.
+  pub fn add_escape_frame(&mut self, f: Val) -> Val {
+    self.marker_count += 1;
+    let escape = Val::Escape(self.marker_count);
+    self.replace_stackframe(vec![
+      Val::Builtin(false, escape_frame_cb),
+      escape.clone(),
+      Val::nil(),
+    ]);
+    self.get_stackframe().pc = 2;
+    self.add_stackframe(vec![f, escape.clone()]);
+    self.get_stackframe().pc = 2;
+    escape
+  }
+
+  /// Returns `val` from the frame an escape was made for, dropping every
+  /// frame above it. Fails once that frame has returned.
+  pub fn escape(&mut self, id: usize, val: Val) {
+    let target = self.stack.iter().rposition(|frame| {
+      frame.accum.len() == 3 &&
+        is_builtin(&frame.accum[0], escape_frame_cb) &&
+        frame.accum[1] == Val::Escape(id)
+    });
+
+    match target {
+      Some(target) => {
+        let transfer = vec![Val::Escape(id), val.clone()];
+        if self.unwind(target + 1, transfer, &val, &[]) {
+          self.return_stackframe(val);
+        }
+      },
+      None => self.error("Escape used after its call/ec returned"),
+    }
+  }
+
   pub fn step(&mut self) -> Option<Val> {
+    if self.interrupt_ready() {
+      self.dispatch_interrupt();
+    }
+
     if !self.stack.is_empty() {
       self.step_inner();
+      self.check_deadline();
     }
 
     if self.stack.is_empty() {
+      self.clear_deadline();
+      self.finish_interrupt();
+      if !self.vars.unpinned.is_empty() {
+        self.collect_unpinned();
+      }
       if self.back_stack.is_empty() {
         return Some(self.result.clone());
       } else {
+        self.cancelled = None;
         self.stack = self.back_stack.pop().unwrap();
         return None;
       }
@@ -367,6 +725,9 @@ impl State {
       Val::Sym(sym) => {
         if let Some(val) = self.vars.get(frame.vars, &sym) {
           frame.accum[frame.pc] = val.clone();
+        } else if self.denied.contains(&sym) {
+          self.error(&format!("Capability denied: {}", sym));
+          return;
         }
         frame.pc += 1;
       },
@@ -382,7 +743,10 @@ impl State {
   }
 
   pub fn set_program(&mut self, val: Val) {
-    self.stack.clear();
+    let stack = std::mem::take(&mut self.stack);
+    self.finish_running_generators(&stack);
+    self.clear_deadline();
+    self.cancelled = None;
 
     match &val {
       Val::List(list) => {
@@ -417,6 +781,7 @@ impl State {
       accum: prog,
       pc: 0,
       vars: root,
+      retained: vec![],
     }];
     
     if self.back_stack.is_empty() {
@@ -431,7 +796,8 @@ impl State {
   }
 
   pub fn running(&self) -> bool {
-    (!self.stack.is_empty() || !self.back_stack.is_empty()) && self.message_peek().is_none()
+    (!self.stack.is_empty() || !self.back_stack.is_empty()) && self.message_peek().is_none() ||
+      self.interrupt_ready()
   }
 
   pub fn finished(&self) -> bool {
@@ -450,13 +816,23 @@ impl State {
     None
   }
 
+  /// Runs `val` right away, resuming the running program once it is done.
+  /// See `queue_interrupt` for interrupts that respect priorities and
+  /// `without-interrupts`.
   pub fn interrupt(&mut self, val: Val) {
     self.back_stack.push(self.stack.clone());
     self.set_program(val);
   }
 
-  pub fn message_add(&mut self, message: &str) {
+  /// Adds a message scripts can send to the host. Returns false, leaving
+  /// it denied, if the profile doesn't allow it.
+  pub fn message_add(&mut self, message: &str) -> bool {
+    if !self.profile.allows_message(message) {
+      self.denied.insert(message.to_string());
+      return false;
+    }
     self.set_var(&message.to_string(), Val::Message(message.to_string()));
+    true
   }
 
   pub fn message_peek(&self) -> Option<Vec<Val>> {
@@ -480,6 +856,13 @@ impl State {
     }
   }
 
+  /// Like `message_peek`, but with the message arguments split into
+  /// positional and keyword arguments.
+  pub fn message_peek_args(&self) -> Option<(String, Args)> {
+    let message = self.message_peek()?;
+    Some((read_string(&message[0]), read_args(&message[1..])))
+  }
+
   pub fn message_return(&mut self, val: Val) {
     let frame = self.get_stackframe();
     if frame.accum.is_empty() {
@@ -498,6 +881,30 @@ impl State {
   }
 }
 
+/// The arguments a rest parameter collects once `bound` positional ones
+/// are taken: the later positional ones and every keyword argument, in the
+/// order they were passed.
+fn rest_args(params: &[Val], bound: usize) -> Vec<Val> {
+  let mut rest = vec![];
+  let mut positional = 0;
+  let mut iter = params.iter();
+  while let Some(param) = iter.next() {
+    match param {
+      Val::Keyword(_) => {
+        rest.push(param.clone());
+        rest.extend(iter.next().cloned());
+      },
+      _ => {
+        if positional >= bound {
+          rest.push(param.clone());
+        }
+        positional += 1;
+      },
+    }
+  }
+  rest
+}
+
 pub fn eval(val: Val) -> Val {
   let mut state = State::new();
   eval_s(&val, &mut state)
@@ -0,0 +1,300 @@
+use crate::val::Val;
+
+/// Formats `args` by the Common Lisp style directives in `control`, as the
+/// `format` builtin does:
+///
+/// - `~a` and `~s` write a value for people (strings bare) or for the
+///   reader (strings quoted). `~10a` pads on the right to 10 columns, and
+///   `~10@a` on the left.
+/// - `~d`, `~b`, `~o` and `~x` write integers in base 10, 2, 8 and 16.
+///   `~5,'0d` pads on the left to 5 columns with zeros, `~@d` always
+///   writes a sign and `~:d` groups digits with commas.
+/// - `~w,df` writes a number with `d` decimals, padded to `w` columns.
+/// - `~{...~}` repeats its body over the elements of a list argument, and
+///   `~^` inside it stops once the elements run out.
+/// - `~%` is a newline and `~~` a tilde.
+///
+/// Missing arguments are an error, and arguments left over are ignored.
+/// Numeric parameters are capped at `MAX_PARAM`, so a script can't ask
+/// for more padding or newlines than fit in memory.
+pub fn format(control: &str, args: &[Val]) -> Result<String, String> {
+  let control: Vec<char> = control.chars().collect();
+  let mut out = String::new();
+  let mut args = ArgList { args, next: 0 };
+  run(&control, &mut args, &mut out)?;
+  Ok(out)
+}
+
+/// The largest width, count or number of decimals a directive takes.
+pub const MAX_PARAM: i64 = 1000;
+
+struct ArgList<'a> {
+  args: &'a [Val],
+  next: usize,
+}
+
+impl<'a> ArgList<'a> {
+  fn pop(&mut self) -> Result<&'a Val, String> {
+    let arg = self.args.get(self.next).ok_or("Not enough arguments for format")?;
+    self.next += 1;
+    Ok(arg)
+  }
+
+  fn is_empty(&self) -> bool {
+    self.next >= self.args.len()
+  }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Param {
+  Num(i64),
+  Char(char),
+}
+
+struct Directive {
+  params: Vec<Option<Param>>,
+  colon: bool,
+  at: bool,
+  name: char,
+  // index just past the directive
+  end: usize,
+}
+
+impl Directive {
+  fn num(&self, index: usize, default: i64) -> i64 {
+    match self.params.get(index) {
+      Some(Some(Param::Num(num))) => (*num).clamp(-MAX_PARAM, MAX_PARAM),
+      _ => default,
+    }
+  }
+
+  fn char(&self, index: usize, default: char) -> char {
+    match self.params.get(index) {
+      Some(Some(Param::Char(c))) => *c,
+      _ => default,
+    }
+  }
+}
+
+/// Whether running a control string got to its end, or stopped at `~^`.
+#[derive(PartialEq)]
+enum Flow {
+  Done,
+  Stop,
+}
+
+fn run(control: &[char], args: &mut ArgList, out: &mut String) -> Result<Flow, String> {
+  let mut i = 0;
+  while i < control.len() {
+    if control[i] != '~' {
+      out.push(control[i]);
+      i += 1;
+      continue;
+    }
+
+    let directive = read_directive(control, i + 1)?;
+    i = directive.end;
+    match directive.name {
+      'a' => {
+        let text = display(args.pop()?);
+        pad(out, &text, &directive);
+      },
+      's' => {
+        let text = args.pop()?.to_string();
+        pad(out, &text, &directive);
+      },
+      'd' => write_integer(out, args.pop()?, 10, &directive),
+      'b' => write_integer(out, args.pop()?, 2, &directive),
+      'o' => write_integer(out, args.pop()?, 8, &directive),
+      'x' => write_integer(out, args.pop()?, 16, &directive),
+      'f' => write_float(out, args.pop()?, &directive),
+      '%' => {
+        for _ in 0..directive.num(0, 1) {
+          out.push('\n');
+        }
+      },
+      '~' => out.push('~'),
+      '\n' => {
+        while i < control.len() && control[i].is_whitespace() {
+          i += 1;
+        }
+      },
+      '^' => {
+        if args.is_empty() {
+          return Ok(Flow::Stop);
+        }
+      },
+      '{' => {
+        let close = find_close(control, i)?;
+        let body = &control[i..close.0];
+        i = close.1;
+        let items = match args.pop()? {
+          Val::List(items) => items.as_slice(),
+          _ => return Err("~{ expects a list".to_string()),
+        };
+        let mut items = ArgList { args: items, next: 0 };
+        while !items.is_empty() {
+          let start = items.next;
+          if run(body, &mut items, out)? == Flow::Stop || items.next == start {
+            break;
+          }
+        }
+      },
+      '}' => return Err("~} without a matching ~{".to_string()),
+      name => return Err(format!("Unknown format directive ~{}", name)),
+    }
+  }
+  Ok(Flow::Done)
+}
+
+/// Reads the parameters, modifiers and name of a directive starting after
+/// its `~`.
+fn read_directive(control: &[char], start: usize) -> Result<Directive, String> {
+  let mut i = start;
+  let mut params = vec![];
+  loop {
+    let param = match control.get(i) {
+      Some('\'') => {
+        let c = *control.get(i + 1).ok_or("Unfinished format directive")?;
+        i += 2;
+        Some(Param::Char(c))
+      },
+      Some(c) if c.is_ascii_digit() || *c == '-' || *c == '+' => {
+        let digits_start = i;
+        i += 1;
+        while control.get(i).is_some_and(|c| c.is_ascii_digit()) {
+          i += 1;
+        }
+        let digits: String = control[digits_start..i].iter().collect();
+        let num = digits.parse().map_err(|_| format!("Bad format parameter {}", digits))?;
+        Some(Param::Num(num))
+      },
+      _ => None,
+    };
+    if control.get(i) == Some(&',') {
+      params.push(param);
+      i += 1;
+    } else {
+      if param.is_some() {
+        params.push(param);
+      }
+      break;
+    }
+  }
+
+  let mut colon = false;
+  let mut at = false;
+  while let Some(c) = control.get(i) {
+    match c {
+      ':' => colon = true,
+      '@' => at = true,
+      _ => break,
+    }
+    i += 1;
+  }
+
+  let name = control.get(i).ok_or("Unfinished format directive")?.to_ascii_lowercase();
+  Ok(Directive { params, colon, at, name, end: i + 1 })
+}
+
+/// Finds the `~}` closing a `~{` whose body starts at `start`, returning
+/// where the `~}` starts and ends.
+fn find_close(control: &[char], start: usize) -> Result<(usize, usize), String> {
+  let mut depth = 0;
+  let mut i = start;
+  while i < control.len() {
+    if control[i] == '~' {
+      let directive = read_directive(control, i + 1)?;
+      match directive.name {
+        '{' => depth += 1,
+        '}' if depth == 0 => return Ok((i, directive.end)),
+        '}' => depth -= 1,
+        _ => {},
+      }
+      i = directive.end;
+    } else {
+      i += 1;
+    }
+  }
+  Err("~{ without a matching ~}".to_string())
+}
+
+fn display(val: &Val) -> String {
+  match val {
+    Val::String(string) => string.clone(),
+    Val::Char(c) => c.to_string(),
+    _ => val.to_string(),
+  }
+}
+
+/// Pads `text` with spaces to the column count of a `~a` or `~s`, on the
+/// left with the `@` modifier.
+fn pad(out: &mut String, text: &str, directive: &Directive) {
+  let width = directive.num(0, 0).max(0) as usize;
+  let padding: String = std::iter::repeat_n(directive.char(3, ' '), width.saturating_sub(text.chars().count())).collect();
+  if directive.at {
+    out.push_str(&padding);
+    out.push_str(text);
+  } else {
+    out.push_str(text);
+    out.push_str(&padding);
+  }
+}
+
+fn pad_left(out: &mut String, text: &str, width: i64, padchar: char) {
+  let width = width.max(0) as usize;
+  out.extend(std::iter::repeat_n(padchar, width.saturating_sub(text.chars().count())));
+  out.push_str(text);
+}
+
+fn write_integer(out: &mut String, val: &Val, radix: u32, directive: &Directive) {
+  let num = match val {
+    Val::Num(num) if num.fract() == 0.0 => *num as i64,
+    _ => {
+      pad_left(out, &display(val), directive.num(0, 0), directive.char(1, ' '));
+      return;
+    },
+  };
+
+  let mut digits = vec![];
+  let mut rest = num.unsigned_abs();
+  loop {
+    digits.push(char::from_digit((rest % radix as u64) as u32, radix).unwrap());
+    rest /= radix as u64;
+    if rest == 0 {
+      break;
+    }
+  }
+  let mut text = String::new();
+  if num < 0 {
+    text.push('-');
+  } else if directive.at {
+    text.push('+');
+  }
+  for (i, digit) in digits.iter().enumerate().rev() {
+    text.push(*digit);
+    if directive.colon && i > 0 && i % 3 == 0 {
+      text.push(directive.char(2, ','));
+    }
+  }
+  pad_left(out, &text, directive.num(0, 0), directive.char(1, ' '));
+}
+
+fn write_float(out: &mut String, val: &Val, directive: &Directive) {
+  let num = match val {
+    Val::Num(num) => *num,
+    _ => {
+      pad_left(out, &display(val), directive.num(0, 0), ' ');
+      return;
+    },
+  };
+
+  let mut text = match directive.params.get(1) {
+    Some(Some(Param::Num(_))) => format!("{:.*}", directive.num(1, 0).max(0) as usize, num),
+    _ => num.to_string(),
+  };
+  if directive.at && num >= 0.0 {
+    text.insert(0, '+');
+  }
+  pad_left(out, &text, directive.num(0, 0), directive.char(4, ' '));
+}
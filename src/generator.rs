@@ -0,0 +1,204 @@
+use crate::{val::Val, exec::{State, Stackframe}, cancel::RunOutcome, builtins::{generator_frame_cb, is_builtin}};
+
+/// A lambda run lazily through `yield`. Between calls its frames are kept
+/// here rather than on the stack.
+#[derive(Clone, Debug)]
+pub struct Generator {
+  pub f: Val,
+  pub stack: Vec<Stackframe>,
+  pub started: bool,
+  pub done: bool,
+}
+
+// the frame a generator is resumed from is (generator-frame g value), and
+// stays at pc 2 while the generator runs
+const GENERATOR_PC: usize = 2;
+
+/// The id of the generator running above a frame, if the frame is the
+/// one it was resumed from.
+pub(crate) fn generator_id(frame: &Stackframe) -> Option<usize> {
+  if frame.pc != GENERATOR_PC || !is_builtin(&frame.accum[0], generator_frame_cb) {
+    return None;
+  }
+  match frame.accum[1] {
+    Val::Generator(id) => Some(id),
+    _ => None,
+  }
+}
+
+impl State {
+  /// Creates a generator running `f`, which takes no arguments.
+  pub fn add_generator(&mut self, f: Val) -> Val {
+    // frames of the generator live in scopes under the closure, which must
+    // outlast whoever made the generator, until it finishes
+    if let Val::Lambda(_, scope, _) = &f {
+      self.vars.pin(*scope);
+    }
+    self.marker_count += 1;
+    self.generators.insert(self.marker_count, Generator {
+      f,
+      stack: vec![],
+      started: false,
+      done: false,
+    });
+    Val::Generator(self.marker_count)
+  }
+
+  /// Whether a generator has finished. Finished generators are forgotten,
+  /// so unknown ids count as finished.
+  pub fn generator_done(&self, id: usize) -> bool {
+    self.generators.get(&id).is_none_or(|generator| generator.done)
+  }
+
+  /// Forgets a generator that has finished, or was left by an error or
+  /// another non-local exit, releasing its closure.
+  pub(crate) fn finish_generator(&mut self, id: usize) {
+    if let Some(generator) = self.generators.remove(&id) {
+      if let Val::Lambda(_, scope, _) = generator.f {
+        self.unpin_scope(scope);
+      }
+    }
+  }
+
+  /// Finishes the generators running in `frames`, a stack dropped without
+  /// being unwound.
+  pub(crate) fn finish_running_generators(&mut self, frames: &[Stackframe]) {
+    let running: Vec<usize> = frames.iter().filter_map(generator_id).collect();
+    for id in running {
+      self.finish_generator(id);
+    }
+  }
+
+  /// Drops a generator that won't be resumed again, releasing its closure
+  /// without running it to the end. A generator that is running can't be
+  /// closed. Returns whether the generator was closed.
+  pub fn close_generator(&mut self, generator: &Val) -> bool {
+    let Val::Generator(id) = generator else {
+      return false;
+    };
+    let running = self.stack.iter()
+      .chain(self.back_stack.iter().flatten())
+      .any(|frame| generator_id(frame) == Some(*id));
+    if running || !self.generators.contains_key(id) {
+      return false;
+    }
+    self.finish_generator(*id);
+    true
+  }
+
+  /// Runs a generator from the top frame until it yields or finishes. The
+  /// top frame becomes the one the value is returned from, and `val` is
+  /// returned from the `yield` the generator stopped at.
+  pub fn resume_generator(&mut self, id: usize, val: Val) {
+    let Some(generator) = self.generators.get_mut(&id).filter(|generator| !generator.done) else {
+      self.return_stackframe(Val::nil());
+      return;
+    };
+
+    let started = generator.started;
+    let f = generator.f.clone();
+    let stack = std::mem::take(&mut generator.stack);
+    generator.started = true;
+
+    self.replace_stackframe(vec![
+      Val::Builtin(false, generator_frame_cb),
+      Val::Generator(id),
+      Val::nil(),
+    ]);
+    self.get_stackframe().pc = GENERATOR_PC;
+
+    if started {
+      self.stack.extend(stack);
+      self.return_stackframe(val);
+    } else {
+      self.add_stackframe(vec![f]);
+      self.get_stackframe().pc = 1;
+    }
+  }
+
+  /// Suspends the innermost running generator, returning `val` from the
+  /// call that resumed it. Outside of a generator, the whole program is
+  /// suspended like a message named `yield`, for the host to pick up.
+  pub fn yield_value(&mut self, val: Val) {
+    let boundary = self.stack.iter().rposition(|frame| generator_id(frame).is_some());
+
+    match boundary {
+      Some(boundary) => {
+        let stack = self.stack.split_off(boundary + 1);
+        let id = generator_id(&self.stack[boundary]).unwrap();
+        if let Some(generator) = self.generators.get_mut(&id) {
+          generator.stack = stack;
+        }
+        self.return_stackframe(val);
+      },
+
+      None => {
+        let frame = self.get_stackframe();
+        frame.accum = vec![Val::Message("yield".to_string()), val];
+        frame.pc = frame.accum.len();
+      },
+    }
+  }
+
+  /// The value the program is suspended on with a top level `yield`.
+  pub fn yield_peek(&self) -> Option<Val> {
+    match self.message_peek() {
+      Some(message) if message[0] == Val::Sym("yield".to_string()) => {
+        Some(message.get(1).cloned().unwrap_or_default())
+      },
+      _ => None,
+    }
+  }
+
+  /// Iterates over the values the program yields with top level
+  /// `yield`s, running it for at most `steps` steps for each.
+  pub fn yields(&mut self, steps: usize) -> Yields<'_> {
+    Yields { state: self, generator: None, steps }
+  }
+
+  /// Iterates over the values `generator` yields, running it for at most
+  /// `steps` steps for each. Each value is the result of a program calling
+  /// the generator, which replaces the running program.
+  pub fn generator_yields(&mut self, generator: &Val, steps: usize) -> Yields<'_> {
+    Yields { state: self, generator: Some(generator.clone()), steps }
+  }
+}
+
+/// Iterator over the values yielded by a program or a generator; see
+/// `State::yields` and `State::generator_yields`. Ends when the program or
+/// generator finishes. It also ends when the program stops short of a
+/// value, at any other message or once its steps run out, leaving the
+/// program for the host to pick up.
+pub struct Yields<'a> {
+  state: &'a mut State,
+  generator: Option<Val>,
+  steps: usize,
+}
+
+impl Iterator for Yields<'_> {
+  type Item = Val;
+
+  fn next(&mut self) -> Option<Val> {
+    let Some(generator) = &self.generator else {
+      if self.state.yield_peek().is_some() {
+        self.state.message_return(Val::nil());
+      }
+      return match self.state.run_for(self.steps) {
+        RunOutcome::Waiting(_) => self.state.yield_peek(),
+        _ => None,
+      };
+    };
+
+    let Val::Generator(id) = *generator else {
+      return None;
+    };
+    if self.state.generator_done(id) {
+      return None;
+    }
+    self.state.set_program(Val::List(vec![generator.clone()]));
+    match self.state.run_for(self.steps) {
+      RunOutcome::Finished(val) if !self.state.generator_done(id) => Some(val),
+      _ => None,
+    }
+  }
+}
@@ -0,0 +1,199 @@
+/// Decompresses raw deflate data, as zip archives store it. Output past
+/// `limit` bytes is an error, so a small archive can't claim a huge file.
+pub fn inflate(data: &[u8], limit: usize) -> Result<Vec<u8>, String> {
+  let mut bits = Bits { data, pos: 0, buffer: 0, count: 0 };
+  let mut out = vec![];
+  loop {
+    let last = bits.take(1)? == 1;
+    match bits.take(2)? {
+      0 => stored_block(&mut bits, &mut out, limit)?,
+      1 => {
+        let (lengths, distances) = fixed_codes();
+        compressed_block(&mut bits, &mut out, limit, &lengths, &distances)?
+      },
+      2 => {
+        let (lengths, distances) = dynamic_codes(&mut bits)?;
+        compressed_block(&mut bits, &mut out, limit, &lengths, &distances)?
+      },
+      _ => return Err("bad deflate block type".to_string()),
+    }
+    if last {
+      return Ok(out);
+    }
+  }
+}
+
+const LENGTH_BASE: [usize; 29] = [
+  3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+  35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u32; 29] = [
+  0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+  3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [usize; 30] = [
+  1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+  257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u32; 30] = [
+  0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+  7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+// the order code length code lengths come in
+const CODE_LENGTH_ORDER: [usize; 19] = [16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15];
+
+/// Reads bits from the least significant end of each byte first.
+struct Bits<'a> {
+  data: &'a [u8],
+  pos: usize,
+  buffer: u32,
+  count: u32,
+}
+
+impl Bits<'_> {
+  fn take(&mut self, n: u32) -> Result<u32, String> {
+    while self.count < n {
+      let byte = *self.data.get(self.pos).ok_or("deflate data ends early")?;
+      self.pos += 1;
+      self.buffer |= (byte as u32) << self.count;
+      self.count += 8;
+    }
+    let value = self.buffer & ((1u64 << n) - 1) as u32;
+    self.buffer >>= n;
+    self.count -= n;
+    Ok(value)
+  }
+
+  /// Drops the bits left in the current byte.
+  fn align(&mut self) {
+    self.buffer = 0;
+    self.count = 0;
+  }
+}
+
+/// A canonical Huffman code, read one bit at a time.
+struct Huffman {
+  // how many codes have each length
+  counts: [u16; 16],
+  // the symbols, ordered by code
+  symbols: Vec<u16>,
+}
+
+impl Huffman {
+  fn new(lengths: &[u8]) -> Huffman {
+    let mut counts = [0u16; 16];
+    for length in lengths {
+      counts[*length as usize] += 1;
+    }
+    counts[0] = 0;
+    let mut symbols: Vec<u16> = (0..lengths.len() as u16).filter(|symbol| lengths[*symbol as usize] != 0).collect();
+    symbols.sort_by_key(|symbol| lengths[*symbol as usize]);
+    Huffman { counts, symbols }
+  }
+
+  fn decode(&self, bits: &mut Bits) -> Result<u16, String> {
+    let (mut code, mut first, mut index) = (0usize, 0usize, 0usize);
+    for count in &self.counts[1..] {
+      let count = *count as usize;
+      code |= bits.take(1)? as usize;
+      if code < first + count {
+        return Ok(self.symbols[index + code - first]);
+      }
+      index += count;
+      first = (first + count) << 1;
+      code <<= 1;
+    }
+    Err("bad deflate code".to_string())
+  }
+}
+
+fn stored_block(bits: &mut Bits, out: &mut Vec<u8>, limit: usize) -> Result<(), String> {
+  bits.align();
+  let length = bits.take(16)? as usize;
+  let complement = bits.take(16)? as usize;
+  if length != !complement & 0xffff {
+    return Err("bad stored block length".to_string());
+  }
+  let bytes = bits.data.get(bits.pos..bits.pos + length).ok_or("deflate data ends early")?;
+  if out.len() + length > limit {
+    return Err("deflate data is larger than its entry".to_string());
+  }
+  out.extend_from_slice(bytes);
+  bits.pos += length;
+  Ok(())
+}
+
+fn fixed_codes() -> (Huffman, Huffman) {
+  let mut lengths = [8u8; 288];
+  lengths[144..256].fill(9);
+  lengths[256..280].fill(7);
+  (Huffman::new(&lengths), Huffman::new(&[5; 30]))
+}
+
+fn dynamic_codes(bits: &mut Bits) -> Result<(Huffman, Huffman), String> {
+  let literals = bits.take(5)? as usize + 257;
+  let distances = bits.take(5)? as usize + 1;
+  let code_lengths = bits.take(4)? as usize + 4;
+
+  let mut lengths = [0u8; 19];
+  for index in &CODE_LENGTH_ORDER[..code_lengths] {
+    lengths[*index] = bits.take(3)? as u8;
+  }
+  let code = Huffman::new(&lengths);
+
+  let mut lengths = vec![];
+  while lengths.len() < literals + distances {
+    let (length, repeat) = match code.decode(bits)? {
+      symbol @ 0..=15 => (symbol as u8, 1),
+      16 => {
+        let previous = *lengths.last().ok_or("deflate repeats a length before the first")?;
+        (previous, 3 + bits.take(2)?)
+      },
+      17 => (0, 3 + bits.take(3)?),
+      _ => (0, 11 + bits.take(7)?),
+    };
+    lengths.extend(std::iter::repeat_n(length, repeat as usize));
+  }
+  if lengths.len() > literals + distances {
+    return Err("deflate code lengths overrun".to_string());
+  }
+  Ok((Huffman::new(&lengths[..literals]), Huffman::new(&lengths[literals..])))
+}
+
+fn compressed_block(bits: &mut Bits, out: &mut Vec<u8>, limit: usize, lengths: &Huffman, distances: &Huffman) -> Result<(), String> {
+  loop {
+    let symbol = lengths.decode(bits)? as usize;
+    if symbol == 256 {
+      return Ok(());
+    }
+    if symbol < 256 {
+      if out.len() >= limit {
+        return Err("deflate data is larger than its entry".to_string());
+      }
+      out.push(symbol as u8);
+      continue;
+    }
+
+    let index = symbol - 257;
+    if index >= LENGTH_BASE.len() {
+      return Err("bad deflate length".to_string());
+    }
+    let length = LENGTH_BASE[index] + bits.take(LENGTH_EXTRA[index])? as usize;
+    let index = distances.decode(bits)? as usize;
+    if index >= DISTANCE_BASE.len() {
+      return Err("bad deflate distance".to_string());
+    }
+    let distance = DISTANCE_BASE[index] + bits.take(DISTANCE_EXTRA[index])? as usize;
+    if distance > out.len() {
+      return Err("deflate distance is too far back".to_string());
+    }
+    if out.len() + length > limit {
+      return Err("deflate data is larger than its entry".to_string());
+    }
+    // the copy can overlap what it writes, so it goes a byte at a time
+    let start = out.len() - distance;
+    for offset in 0..length {
+      out.push(out[start + offset]);
+    }
+  }
+}
@@ -0,0 +1,115 @@
+use std::collections::HashMap;
+
+use crate::{val::Val, exec::State, builtins::{without_interrupts_frame_cb, is_builtin}};
+
+/// An interrupt waiting for the running program to allow it.
+#[derive(Clone, Debug)]
+pub struct PendingInterrupt {
+  pub source: String,
+  pub priority: i32,
+  pub program: Val,
+}
+
+/// An interrupt that has started. `level` is the length of the back stack
+/// while it runs, and `result` the result it will restore when it ends.
+#[derive(Clone, Debug)]
+pub(crate) struct RunningInterrupt {
+  pub source: String,
+  pub priority: i32,
+  pub level: usize,
+  pub result: Val,
+}
+
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Interrupts {
+  pub sources: HashMap<String, i32>,
+  pub pending: Vec<PendingInterrupt>,
+  pub running: Vec<RunningInterrupt>,
+}
+
+impl State {
+  /// Registers a named interrupt source. An interrupt preempts the running
+  /// program only if its priority is higher than that of the interrupt
+  /// being run, if any. Otherwise it waits its turn.
+  pub fn interrupt_source(&mut self, source: &str, priority: i32) {
+    self.interrupts.sources.insert(source.to_string(), priority);
+  }
+
+  /// Queues `program` to run as an interrupt from `source`. Sources that
+  /// weren't registered have priority 0. Interrupts of equal priority run
+  /// in the order they were queued.
+  pub fn queue_interrupt(&mut self, source: &str, program: Val) {
+    let priority = self.interrupts.sources.get(source).copied().unwrap_or(0);
+    self.interrupts.pending.push(PendingInterrupt {
+      source: source.to_string(),
+      priority,
+      program,
+    });
+  }
+
+  /// The interrupts that haven't started yet.
+  pub fn pending_interrupts(&self) -> &[PendingInterrupt] {
+    &self.interrupts.pending
+  }
+
+  /// The source of the interrupt being run, if any.
+  pub fn current_interrupt(&self) -> Option<&str> {
+    self.interrupts.running.last().map(|running| running.source.as_str())
+  }
+
+  /// Whether the running program is inside a `without-interrupts` form.
+  pub fn interrupts_masked(&self) -> bool {
+    self.stack.iter().any(|frame| {
+      frame.pc == 1 && is_builtin(&frame.accum[0], without_interrupts_frame_cb)
+    })
+  }
+
+  /// The index of the pending interrupt that should start now, if any.
+  fn next_interrupt(&self) -> Option<usize> {
+    let floor = self.interrupts.running.last().map(|running| running.priority);
+    let mut next: Option<usize> = None;
+    for (i, pending) in self.interrupts.pending.iter().enumerate() {
+      if floor.is_some_and(|floor| pending.priority <= floor) {
+        continue;
+      }
+      if next.is_none_or(|next| pending.priority > self.interrupts.pending[next].priority) {
+        next = Some(i);
+      }
+    }
+    next
+  }
+
+  pub(crate) fn interrupt_ready(&self) -> bool {
+    !self.interrupts.pending.is_empty() &&
+      self.next_interrupt().is_some() &&
+      !self.interrupts_masked()
+  }
+
+  /// Starts the next pending interrupt, leaving the running program on the
+  /// back stack to resume once the interrupt is done.
+  pub(crate) fn dispatch_interrupt(&mut self) {
+    let Some(next) = self.next_interrupt() else {
+      return;
+    };
+    let pending = self.interrupts.pending.remove(next);
+    let result = self.result.clone();
+    self.back_stack.push(std::mem::take(&mut self.stack));
+    self.interrupts.running.push(RunningInterrupt {
+      source: pending.source,
+      priority: pending.priority,
+      level: self.back_stack.len(),
+      result,
+    });
+    self.set_program(pending.program);
+  }
+
+  /// Called once the stack is empty, before the back stack is resumed.
+  /// Restores the result from before the interrupt that just finished.
+  pub(crate) fn finish_interrupt(&mut self) {
+    let level = self.back_stack.len();
+    if self.interrupts.running.last().is_some_and(|running| running.level == level) {
+      let running = self.interrupts.running.pop().unwrap();
+      self.result = running.result;
+    }
+  }
+}
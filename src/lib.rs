@@ -1,10 +1,26 @@
 #![crate_type = "lib"]
 
+pub mod builder;
 pub mod builtins;
+pub mod cancel;
+pub mod events;
 pub mod exec;
+pub mod format;
+pub mod generator;
+pub mod inflate;
+pub mod interrupt;
+pub mod loader;
+pub mod modules;
 pub mod object;
+pub mod profile;
+pub mod random;
+pub mod reader;
+pub mod record;
+pub mod timers;
+pub mod unwind;
 pub mod val;
 pub mod variables;
+pub mod vec2;
 
 #[cfg(test)]
 pub mod test;
@@ -12,5 +28,15 @@ pub mod test;
 pub use crate::val::Val;
 pub use crate::val::p;
 pub use crate::exec::{eval, State, eval_s};
-pub use crate::object::{read_object, read_ivec2};
+pub use crate::format::format;
+pub use crate::generator::Yields;
+pub use crate::cancel::RunOutcome;
+pub use crate::interrupt::PendingInterrupt;
+pub use crate::profile::Profile;
+pub use crate::builtins::BuiltinGroup;
+pub use crate::builder::StateBuilder;
+pub use crate::loader::{ScriptLoader, LoadError, FsLoader, MemoryLoader, ArchiveLoader, DenyLoader};
+pub use crate::object::{read_object, try_read_object, write_entry, read_vec2, read_ivec2, read_args, Args};
+pub use crate::vec2::{Vec2, IVec2};
+pub use crate::record::Record;
 
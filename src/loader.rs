@@ -0,0 +1,229 @@
+use std::{collections::HashMap, fmt::{self, Debug, Display, Formatter}, path::{Component, Path, PathBuf}};
+use crate::inflate::inflate;
+
+/// Why a script couldn't be read.
+#[derive(Clone, Debug, PartialEq)]
+pub enum LoadError {
+  NotFound(String),
+  Denied(String),
+  Invalid(String),
+}
+
+impl Display for LoadError {
+  fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+    match self {
+      LoadError::NotFound(path) => write!(f, "Script not found: {}", path),
+      LoadError::Denied(path) => write!(f, "Script loading denied: {}", path),
+      LoadError::Invalid(message) => write!(f, "Invalid script: {}", message),
+    }
+  }
+}
+
+/// Where `load`, `require` and `load_lib_from` read scripts from. Paths always
+/// use `/` and are relative to whatever the loader treats as its root.
+pub trait ScriptLoader: Debug + Send + Sync {
+  fn read(&self, path: &str) -> Result<String, LoadError>;
+}
+
+/// Reads scripts from a directory. Paths that would leave it are denied.
+#[derive(Clone, Debug)]
+pub struct FsLoader {
+  pub root: PathBuf,
+}
+
+impl FsLoader {
+  pub fn new(root: impl Into<PathBuf>) -> FsLoader {
+    FsLoader { root: root.into() }
+  }
+}
+
+impl ScriptLoader for FsLoader {
+  fn read(&self, path: &str) -> Result<String, LoadError> {
+    let escapes = Path::new(path).components().any(|component| {
+      !matches!(component, Component::Normal(_) | Component::CurDir)
+    });
+    if escapes {
+      return Err(LoadError::Denied(path.to_string()));
+    }
+    let not_found = |_| LoadError::NotFound(path.to_string());
+    // a symlink under the root can still lead out of it
+    let full = self.root.join(path).canonicalize().map_err(not_found)?;
+    if !full.starts_with(self.root.canonicalize().map_err(not_found)?) {
+      return Err(LoadError::Denied(path.to_string()));
+    }
+    std::fs::read_to_string(full).map_err(not_found)
+  }
+}
+
+/// Scripts kept in memory, keyed by path.
+#[derive(Clone, Debug, Default)]
+pub struct MemoryLoader {
+  pub files: HashMap<String, String>,
+}
+
+impl MemoryLoader {
+  pub fn new() -> MemoryLoader {
+    MemoryLoader::default()
+  }
+
+  pub fn insert(&mut self, path: &str, source: &str) {
+    self.files.insert(normalize(path), source.to_string());
+  }
+}
+
+impl ScriptLoader for MemoryLoader {
+  fn read(&self, path: &str) -> Result<String, LoadError> {
+    self.files.get(&normalize(path)).cloned().ok_or_else(|| LoadError::NotFound(path.to_string()))
+  }
+}
+
+/// Scripts read from an archive, such as an asset pack. Zip archives may
+/// store their files or deflate them; tar archives must be uncompressed.
+#[derive(Clone, Debug, Default)]
+pub struct ArchiveLoader {
+  pub files: HashMap<String, String>,
+}
+
+impl ArchiveLoader {
+  /// Reads the regular files of a tar archive. Files that aren't valid
+  /// UTF-8 are skipped, since they can't be scripts.
+  pub fn from_tar(bytes: &[u8]) -> Result<ArchiveLoader, LoadError> {
+    const BLOCK: usize = 512;
+    let mut files = HashMap::new();
+    let mut offset = 0;
+
+    while offset + BLOCK <= bytes.len() {
+      let header = &bytes[offset..offset + BLOCK];
+      // the archive ends with empty blocks
+      if header.iter().all(|byte| *byte == 0) {
+        break;
+      }
+
+      let size = read_octal(&header[124..136])
+        .ok_or_else(|| LoadError::Invalid(format!("bad tar header at {}", offset)))?;
+      let mut name = read_field(&header[0..100]);
+      if &header[257..262] == b"ustar" {
+        let prefix = read_field(&header[345..500]);
+        if !prefix.is_empty() {
+          name = format!("{}/{}", prefix, name);
+        }
+      }
+
+      let start = offset + BLOCK;
+      let end = start + size;
+      if end > bytes.len() {
+        return Err(LoadError::Invalid(format!("truncated tar entry {}", name)));
+      }
+      let regular_file = matches!(header[156], b'0' | 0);
+      if regular_file {
+        if let Ok(source) = String::from_utf8(bytes[start..end].to_vec()) {
+          files.insert(normalize(&name), source);
+        }
+      }
+      offset = start + size.div_ceil(BLOCK) * BLOCK;
+    }
+
+    Ok(ArchiveLoader { files })
+  }
+
+  /// Reads the files of a zip archive from its central directory. Files
+  /// that aren't valid UTF-8 are skipped, as in `from_tar`.
+  pub fn from_zip(bytes: &[u8]) -> Result<ArchiveLoader, LoadError> {
+    let invalid = |message: &str| LoadError::Invalid(message.to_string());
+    // the end of central directory record, followed by up to 64k of comment
+    let end = (0..=bytes.len().saturating_sub(22)).rev()
+      .take(65536)
+      .find(|offset| read_u32(bytes, *offset) == Some(0x06054b50))
+      .ok_or_else(|| invalid("no zip directory"))?;
+    let entries = read_u16(bytes, end + 10).ok_or_else(|| invalid("truncated zip directory"))?;
+    let mut offset = read_u32(bytes, end + 16).ok_or_else(|| invalid("truncated zip directory"))? as usize;
+    let mut files = HashMap::new();
+
+    for _ in 0..entries {
+      let header = |at: usize| read_u32(bytes, offset + at).ok_or_else(|| invalid("truncated zip directory"));
+      let header16 = |at: usize| read_u16(bytes, offset + at).ok_or_else(|| invalid("truncated zip directory"));
+      if header(0)? != 0x02014b50 {
+        return Err(invalid("bad zip directory entry"));
+      }
+      let (flags, method) = (header16(8)?, header16(10)?);
+      let (compressed, size) = (header(20)? as usize, header(24)? as usize);
+      let name_length = header16(28)? as usize;
+      let skipped = header16(30)? as usize + header16(32)? as usize;
+      let local = header(42)? as usize;
+      let name = bytes.get(offset + 46..offset + 46 + name_length).ok_or_else(|| invalid("truncated zip directory"))?;
+      let name = String::from_utf8_lossy(name).to_string();
+      offset += 46 + name_length + skipped;
+
+      if name.ends_with('/') {
+        continue;
+      }
+      if flags & 1 != 0 {
+        return Err(LoadError::Invalid(format!("encrypted zip entry {}", name)));
+      }
+      if read_u32(bytes, local) != Some(0x04034b50) {
+        return Err(LoadError::Invalid(format!("bad zip entry {}", name)));
+      }
+      let start = match (read_u16(bytes, local + 26), read_u16(bytes, local + 28)) {
+        (Some(name_length), Some(extra_length)) => local + 30 + name_length as usize + extra_length as usize,
+        _ => return Err(LoadError::Invalid(format!("truncated zip entry {}", name))),
+      };
+      let data = bytes.get(start..start + compressed)
+        .ok_or_else(|| LoadError::Invalid(format!("truncated zip entry {}", name)))?;
+      let contents = match method {
+        0 => data.to_vec(),
+        8 => inflate(data, size).map_err(|message| LoadError::Invalid(format!("{} in zip entry {}", message, name)))?,
+        _ => return Err(LoadError::Invalid(format!("unsupported compression in zip entry {}", name))),
+      };
+      if let Ok(source) = String::from_utf8(contents) {
+        files.insert(normalize(&name), source);
+      }
+    }
+
+    Ok(ArchiveLoader { files })
+  }
+}
+
+impl ScriptLoader for ArchiveLoader {
+  fn read(&self, path: &str) -> Result<String, LoadError> {
+    self.files.get(&normalize(path)).cloned().ok_or_else(|| LoadError::NotFound(path.to_string()))
+  }
+}
+
+/// Refuses to read anything.
+#[derive(Clone, Debug, Default)]
+pub struct DenyLoader;
+
+impl ScriptLoader for DenyLoader {
+  fn read(&self, path: &str) -> Result<String, LoadError> {
+    Err(LoadError::Denied(path.to_string()))
+  }
+}
+
+fn normalize(path: &str) -> String {
+  path.split('/')
+    .filter(|part| !part.is_empty() && *part != ".")
+    .collect::<Vec<_>>()
+    .join("/")
+}
+
+fn read_field(field: &[u8]) -> String {
+  let end = field.iter().position(|byte| *byte == 0).unwrap_or(field.len());
+  String::from_utf8_lossy(&field[..end]).to_string()
+}
+
+fn read_octal(field: &[u8]) -> Option<usize> {
+  let text = read_field(field);
+  let text = text.trim();
+  if text.is_empty() {
+    return Some(0);
+  }
+  usize::from_str_radix(text, 8).ok()
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> Option<u16> {
+  Some(u16::from_le_bytes(bytes.get(offset..offset + 2)?.try_into().ok()?))
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> Option<u32> {
+  Some(u32::from_le_bytes(bytes.get(offset..offset + 4)?.try_into().ok()?))
+}
@@ -3,11 +3,27 @@ use std::io::{self, Write};
 use exec::State;
 use crate::val::p;
 
+pub mod builder;
 pub mod builtins;
+pub mod cancel;
+pub mod events;
 pub mod exec;
+pub mod format;
+pub mod generator;
+pub mod inflate;
+pub mod interrupt;
+pub mod loader;
+pub mod modules;
 pub mod object;
+pub mod profile;
+pub mod random;
+pub mod reader;
+pub mod record;
+pub mod timers;
+pub mod unwind;
 pub mod val;
 pub mod variables;
+pub mod vec2;
 
 fn main() {
   let mut state = State::new();
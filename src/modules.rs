@@ -0,0 +1,190 @@
+use crate::{exec::State, variables::ScopeRef};
+#[cfg(feature = "modules")]
+use crate::{val::{Val, p_all}, loader::LoadError, builtins::{is_builtin, modules::{module_frame_cb, require_frame_cb}}};
+
+/// A module defined with `(module name (provide ...) ...)`. Its bindings
+/// live in its own scope, and only the provided ones can be required.
+#[derive(Clone, Debug)]
+pub struct Module {
+  pub scope: ScopeRef,
+  pub exports: Vec<String>,
+  pub loaded: bool,
+}
+
+impl State {
+  /// Adds a directory of the script loader searched for `name.cnvr` when a
+  /// module `name` is required before it is defined. Directories are
+  /// searched in the order they were added, and the loader's root is
+  /// searched if none were.
+  pub fn add_module_path(&mut self, path: &str) {
+    self.module_paths.push(path.trim_end_matches('/').to_string());
+  }
+
+  /// The module called `name`, if one has been defined.
+  pub fn module(&self, name: &str) -> Option<&Module> {
+    self.modules.get(name)
+  }
+}
+
+#[cfg(feature = "modules")]
+impl State {
+  /// Turns the top frame into one defining a module, with `body` run in a
+  /// fresh scope under the root.
+  pub(crate) fn add_module_frame(&mut self, name: &str, exports: Vec<String>, body: Vec<Val>) {
+    let root = self.vars.root();
+    let scope = self.vars.new_child(root);
+    // closures from the module are called long after it is defined
+    self.vars.pin(scope);
+    let old = self.modules.insert(name.to_string(), Module {
+      scope,
+      exports,
+      loaded: false,
+    });
+    if let Some(old) = old {
+      self.unpin_scope(old.scope);
+    }
+
+    self.replace_stackframe(vec![
+      Val::Builtin(false, module_frame_cb),
+      Val::Sym(name.to_string()),
+      Val::nil(),
+    ]);
+    self.get_stackframe().pc = 2;
+    let mut list = vec![Val::Sym("do".to_string())];
+    list.extend(body);
+    self.add_stackframe(list);
+    self.get_stackframe().vars = scope;
+  }
+
+  pub(crate) fn finish_module(&mut self, name: &str) {
+    if let Some(module) = self.modules.get_mut(name) {
+      module.loaded = true;
+    }
+  }
+
+  /// Forgets module `name`, releasing its scope once nothing refers to it.
+  fn drop_module(&mut self, name: &str) {
+    if let Some(module) = self.modules.remove(name) {
+      self.unpin_scope(module.scope);
+    }
+  }
+
+  /// Whether the body of module `name` is running.
+  fn loading_module(&self, name: &str) -> bool {
+    self.stack.iter().any(|frame| {
+      is_builtin(&frame.accum[0], module_frame_cb) &&
+        matches!(&frame.accum[1], Val::Sym(module) if module == name)
+    })
+  }
+
+  /// Turns the top frame into one requiring module `name`, which binds
+  /// `name/export` for each of its exports in the current scope, and the
+  /// export itself for those listed in `imports`. The module is loaded
+  /// from the module paths first if it hasn't been defined yet.
+  pub(crate) fn require(&mut self, name: &str, imports: Vec<Val>) {
+    self.replace_stackframe(vec![
+      Val::Builtin(false, require_frame_cb),
+      Val::Sym(name.to_string()),
+      Val::List(imports),
+      Val::nil(),
+    ]);
+    self.get_stackframe().pc = 3;
+
+    if self.modules.get(name).is_some_and(|module| !module.loaded) {
+      if self.loading_module(name) {
+        self.error(&format!("Circular require of module {}", name));
+        return;
+      }
+      // its body was left by an error, so it is loaded again
+      self.drop_module(name);
+    }
+
+    match self.modules.get(name) {
+      Some(_) => self.bind_exports(name),
+      None => {
+        let source = match self.find_module(name) {
+          Ok(source) => source,
+          Err(LoadError::NotFound(_)) => {
+            self.error(&format!("Module not found: {}", name));
+            return;
+          },
+          Err(err) => {
+            self.error(&err.to_string());
+            return;
+          },
+        };
+        let mut list = vec![Val::Sym("do".to_string())];
+        match p_all(&source) {
+          Ok(vals) => list.extend(vals),
+          Err(error) => {
+            self.error(&format!("Could not parse module {}: {}", name, error));
+            return;
+          },
+        }
+        self.add_stackframe(list);
+        // definitions outside the module form stay in the file
+        let root = self.vars.root();
+        let scope = self.vars.new_child(root);
+        self.get_stackframe().vars = scope;
+      },
+    }
+  }
+
+  fn find_module(&self, name: &str) -> Result<String, LoadError> {
+    let file = format!("{}.cnvr", name);
+    if self.module_paths.is_empty() {
+      return self.read_script(&file);
+    }
+
+    for path in self.module_paths.iter() {
+      let path = if path.is_empty() { file.clone() } else { format!("{}/{}", path, file) };
+      match self.read_script(&path) {
+        Err(LoadError::NotFound(_)) => continue,
+        result => return result,
+      }
+    }
+    Err(LoadError::NotFound(file))
+  }
+
+  /// Binds the exports of module `name` for the require frame on top.
+  pub(crate) fn bind_exports(&mut self, name: &str) {
+    let Some(module) = self.modules.get(name) else {
+      self.error(&format!("Module not found: {}", name));
+      return;
+    };
+    if !module.loaded {
+      self.error(&format!("Module {} did not finish loading", name));
+      return;
+    }
+
+    let frame = self.stack.last().unwrap();
+    let imports: Vec<String> = match &frame.accum[2] {
+      Val::List(imports) => imports.iter().map(|import| import.to_string()).collect(),
+      _ => vec![],
+    };
+    let target = frame.vars;
+
+    let mut bindings = vec![];
+    for export in module.exports.iter() {
+      match self.vars.get(module.scope, export) {
+        Some(val) => bindings.push((export.clone(), val.clone())),
+        None => {
+          self.error(&format!("Module {} does not define {}", name, export));
+          return;
+        },
+      }
+    }
+    if let Some(import) = imports.iter().find(|import| !module.exports.contains(import)) {
+      self.error(&format!("Module {} does not provide {}", name, import));
+      return;
+    }
+
+    for (export, val) in bindings {
+      if imports.contains(&export) {
+        self.vars.set(target, &export, val.clone());
+      }
+      self.vars.set(target, &format!("{}/{}", name, export), val);
+    }
+    self.return_stackframe(Val::Sym(name.to_string()));
+  }
+}
@@ -1,48 +1,122 @@
-use crate::val::Val;
+use crate::{val::Val, vec2::{Vec2, IVec2}};
 
-pub fn read_object(object: &Val, mut f: impl FnMut(&str, &Val)) {
+pub fn read_object(object: &Val, f: impl FnMut(&str, &Val)) {
+  if let Err(err) = try_read_object(object, f) {
+    panic!("{}", err);
+  }
+}
+
+/// Like `read_object`, but returns an error instead of panicking on data
+/// that isn't an object. `f` may have been called for the entries before
+/// the invalid one.
+pub fn try_read_object(object: &Val, mut f: impl FnMut(&str, &Val)) -> Result<(), String> {
+  let list = match object {
+    Val::List(list) => list,
+    _ => return Err("Invalid object".to_string()),
+  };
+  for item in list {
+    let Some(key) = entry_key(item)? else { continue };
+    if let Val::List(entry) = item {
+      match entry.len() {
+        1 => f(key, &Val::nil()),
+        2 => f(key, &entry[1]),
+        _ => f(key, &Val::List(entry[1..].to_vec())),
+      };
+    }
+  }
+  Ok(())
+}
+
+/// The key of an object entry, or None for an empty entry, which objects
+/// skip.
+pub fn entry_key(entry: &Val) -> Result<Option<&str>, String> {
+  match entry {
+    Val::List(list) => match list.first() {
+      None => Ok(None),
+      Some(Val::Sym(key)) => Ok(Some(key)),
+      Some(_) => Err("Invalid object property key".to_string()),
+    },
+    _ => Err("Invalid object property".to_string()),
+  }
+}
+
+/// The entry `read_object` reads back as `key` and `value`. Lists of more
+/// than one item are spliced in, as in `(size 1 1)`.
+pub fn write_entry(key: &str, value: &Val) -> Val {
+  let mut entry = vec![Val::Sym(key.to_string())];
+  match value {
+    Val::List(list) if list.len() >= 2 => entry.extend(list.iter().cloned()),
+    _ => entry.push(value.clone()),
+  }
+  Val::List(entry)
+}
+
+/// Reads a position from a vec2 of either flavour or a list of two numbers.
+pub fn read_vec2(object: &Val) -> Option<Vec2> {
   match object {
-    Val::List(list) => {
-      for item in list {
-        match item {
-          Val::List(list) => {
-            if list.len() == 0 {
-              continue;
-            }
-            let key = match &list[0] {
-              Val::Sym(key) => key,
-              _ => panic!("Invalid object property key"),
-            };
-            match list.len() {
-              1 => f(key, &Val::nil()),
-              2 => f(key, &list[1]),
-              _ => f(key, &Val::List(list[1..].to_vec())),
-            };
-          },
-          _ => panic!("Invalid object property"),
-        }
-      }
+    Val::Vec2(v) => Some(*v),
+    Val::IVec2(v) => Some((*v).into()),
+    Val::List(list) => match list.as_slice() {
+      [Val::Num(x), Val::Num(y), ..] => Some(Vec2::new(*x, *y)),
+      _ => None,
     },
-    _ => panic!("Invalid object"),
+    _ => None,
   }
 }
 
-pub fn read_ivec2(object: &Val, success: impl FnOnce(i32, i32), failure: impl FnOnce()) {
-  if let Val::List(list) = object {
-    if list.len() >= 2 {
-      if let (Val::Num(x), Val::Num(y)) = (&list[0], &list[1]) {
-        success(*x as i32, *y as i32);
-        return;
-      }
-    }
+/// Like `read_vec2`, truncating float coordinates to integers.
+pub fn read_ivec2(object: &Val) -> Option<IVec2> {
+  match object {
+    Val::IVec2(v) => Some(*v),
+    _ => read_vec2(object).map(|v| IVec2::new(v.x as i32, v.y as i32)),
   }
-  failure();
 }
 
 pub fn read_string(object: &Val) -> String {
   match object {
     Val::Sym(sym) => sym.to_string(),
     Val::String(str) => str.to_string(),
+    Val::Char(c) => c.to_string(),
     _ => format!("{:?}", object)
   }
 }
+
+/// Call arguments split into positional values and `#:keyword value` pairs.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Args {
+  pub positional: Vec<Val>,
+  pub keywords: Vec<(String, Val)>,
+}
+
+impl Args {
+  pub fn get(&self, key: &str) -> Option<&Val> {
+    self.keywords.iter().find(|(k, _)| k == key).map(|(_, val)| val)
+  }
+
+  pub fn has(&self, key: &str) -> bool {
+    self.get(key).is_some()
+  }
+
+  /// The argument given as `#:key`, or else the positional one at `index`,
+  /// for builtins that take either.
+  pub fn named(&self, key: &str, index: usize) -> Option<&Val> {
+    self.get(key).or_else(|| self.positional.get(index))
+  }
+}
+
+/// Splits a flat argument list into an `Args`. Every keyword takes the value
+/// that follows it; a keyword at the very end is a flag and reads as `t`.
+pub fn read_args(args: &[Val]) -> Args {
+  let mut result = Args::default();
+  let mut iter = args.iter();
+  while let Some(arg) = iter.next() {
+    match arg {
+      Val::Keyword(key) => {
+        let val = iter.next().cloned().unwrap_or_else(Val::truth);
+        result.keywords.push((key.to_string(), val));
+      },
+      _ => result.positional.push(arg.clone()),
+    }
+  }
+  result
+}
@@ -0,0 +1,101 @@
+use crate::{val::Val, exec::State, builtins::{BuiltinGroup, Callback}};
+
+/// What scripts run by a `State` may use: its builtin groups, and the
+/// messages and host functions the host adds. Anything left out is
+/// denied, and referring to it is an error rather than an unbound symbol.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Profile {
+  pub groups: Vec<BuiltinGroup>,
+  /// The messages `message_add` may add, or `None` for any.
+  pub messages: Option<Vec<String>>,
+  /// The host functions `host_function_add` may add, or `None` for any.
+  pub host_functions: Option<Vec<String>>,
+}
+
+impl Profile {
+  /// Every builtin, message and host function.
+  pub fn trusted() -> Profile {
+    Profile {
+      groups: BuiltinGroup::ALL.to_vec(),
+      messages: None,
+      host_functions: None,
+    }
+  }
+
+  /// For untrusted scripts such as mods: no way to read scripts or touch
+  /// the host's programs, and no messages or host functions unless they
+  /// are allowed one by one.
+  pub fn sandboxed() -> Profile {
+    Profile {
+      groups: vec![
+        BuiltinGroup::Core,
+        BuiltinGroup::Math,
+        BuiltinGroup::Strings,
+        BuiltinGroup::Lists,
+        BuiltinGroup::Generators,
+        BuiltinGroup::Events,
+        BuiltinGroup::Timers,
+        BuiltinGroup::Modules,
+      ],
+      messages: Some(vec![]),
+      host_functions: Some(vec![]),
+    }
+  }
+
+  pub fn allow_group(mut self, group: BuiltinGroup) -> Profile {
+    if !self.groups.contains(&group) {
+      self.groups.push(group);
+    }
+    self
+  }
+
+  pub fn allow_message(mut self, message: &str) -> Profile {
+    if let Some(messages) = &mut self.messages {
+      messages.push(message.to_string());
+    }
+    self
+  }
+
+  pub fn allow_host_function(mut self, name: &str) -> Profile {
+    if let Some(host_functions) = &mut self.host_functions {
+      host_functions.push(name.to_string());
+    }
+    self
+  }
+
+  pub fn allows_message(&self, message: &str) -> bool {
+    self.messages.as_ref().is_none_or(|messages| messages.iter().any(|allowed| allowed == message))
+  }
+
+  pub fn allows_host_function(&self, name: &str) -> bool {
+    self.host_functions.as_ref().is_none_or(|names| names.iter().any(|allowed| allowed == name))
+  }
+}
+
+impl Default for Profile {
+  fn default() -> Profile {
+    Profile::trusted()
+  }
+}
+
+impl State {
+  pub fn profile(&self) -> &Profile {
+    &self.profile
+  }
+
+  /// Whether scripts are denied the capability called `name`.
+  pub fn is_denied(&self, name: &str) -> bool {
+    self.denied.contains(name)
+  }
+
+  /// Adds a builtin the host provides, which scripts call like any other.
+  /// Returns false, leaving it denied, if the profile doesn't allow it.
+  pub fn host_function_add(&mut self, name: &str, callback: Callback) -> bool {
+    if !self.profile.allows_host_function(name) {
+      self.denied.insert(name.to_string());
+      return false;
+    }
+    self.set_var(&name.to_string(), Val::Builtin(false, callback));
+    true
+  }
+}
@@ -0,0 +1,54 @@
+use crate::exec::State;
+
+/// A splitmix64 generator. Its whole state is one number, so a cloned
+/// `State` replays exactly the same rolls as the original.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rng {
+  pub seed: u64,
+  state: u64,
+}
+
+impl Default for Rng {
+  fn default() -> Rng {
+    Rng::new(0)
+  }
+}
+
+impl Rng {
+  pub fn new(seed: u64) -> Rng {
+    Rng { seed, state: seed }
+  }
+
+  pub fn next_u64(&mut self) -> u64 {
+    self.state = self.state.wrapping_add(0x9e3779b97f4a7c15);
+    let mut z = self.state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d049bb133111eb);
+    z ^ (z >> 31)
+  }
+
+  /// A number in [0, 1).
+  pub fn next_f32(&mut self) -> f32 {
+    // the top 24 bits, as many as an f32 holds exactly
+    (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+  }
+
+  /// An index in [0, n), for n > 0.
+  pub fn below(&mut self, n: usize) -> usize {
+    // widening multiply instead of modulo, so small n aren't biased
+    ((self.next_u64() as u128 * n as u128) >> 64) as usize
+  }
+}
+
+impl State {
+  /// Restarts the random numbers scripts see from `seed`. States seeded
+  /// the same make the same rolls, for replays and lockstep games.
+  pub fn seed_rng(&mut self, seed: u64) {
+    self.rng = Rng::new(seed);
+  }
+
+  /// The seed last given to `seed_rng`, 0 by default.
+  pub fn rng_seed(&self) -> u64 {
+    self.rng.seed
+  }
+}
@@ -0,0 +1,210 @@
+use crate::{val::{Val, read_char}, vec2::{Vec2, IVec2}, record::Record, object::try_read_object};
+
+/// A piece of source text. Strings are read whole here, so nothing inside
+/// them is mistaken for syntax, whatever chars they hold.
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+  Open,
+  Close,
+  Quote,
+  Comma,
+  Str(String),
+  Atom(String),
+  /// `#v`, `#iv` or `#record` right before a `(`, which make a literal
+  /// out of the list.
+  Tag(String),
+}
+
+fn is_delimiter(c: char) -> bool {
+  c.is_whitespace() || matches!(c, '(' | ')' | '\'' | ',' | ';')
+}
+
+/// Splits `source` into tokens. Comments run from `;` to the end of the
+/// line. On an unclosed string, returns the tokens before it along with
+/// the error.
+fn tokenize(source: &str) -> (Vec<Token>, Option<String>) {
+  let mut tokens = vec![];
+  let mut chars = source.char_indices().peekable();
+  while let Some((start, c)) = chars.next() {
+    match c {
+      '(' => tokens.push(Token::Open),
+      ')' => tokens.push(Token::Close),
+      '\'' => tokens.push(Token::Quote),
+      ',' => tokens.push(Token::Comma),
+      ';' => {
+        while chars.next_if(|(_, c)| *c != '\n').is_some() {}
+      },
+      '"' => {
+        let mut string = String::new();
+        loop {
+          match chars.next() {
+            Some((_, '"')) => break,
+            Some((_, c)) => string.push(c),
+            None => return (tokens, Some(format!("Unclosed string at {}", start))),
+          }
+        }
+        tokens.push(Token::Str(string));
+      },
+      c if c.is_whitespace() => {},
+      c => {
+        let mut atom = c.to_string();
+        // the char after `#\` is taken as is, so `#\(` is a char
+        if c == '#' && chars.next_if(|(_, c)| *c == '\\').is_some() {
+          atom.push('\\');
+          if let Some((_, c)) = chars.next() {
+            atom.push(c);
+          }
+        }
+        while let Some((_, c)) = chars.next_if(|(_, c)| !is_delimiter(*c)) {
+          atom.push(c);
+        }
+        let is_tag = matches!(atom.as_str(), "#v" | "#iv" | "#record");
+        if is_tag && chars.peek().is_some_and(|(_, c)| *c == '(') {
+          tokens.push(Token::Tag(atom));
+        } else {
+          tokens.push(Token::Atom(atom));
+        }
+      },
+    }
+  }
+  (tokens, None)
+}
+
+/// Reads a number: digits with an optional leading `-`, fraction and
+/// exponent, as in `-1.5e-3`.
+fn read_number(atom: &str) -> Option<f32> {
+  let digits = atom.strip_prefix('-').unwrap_or(atom);
+  let (mantissa, exponent) = match digits.split_once(['e', 'E']) {
+    Some((mantissa, exponent)) => (mantissa, Some(exponent)),
+    None => (digits, None),
+  };
+  let (whole, fraction) = match mantissa.split_once('.') {
+    Some((whole, fraction)) => (whole, Some(fraction)),
+    None => (mantissa, None),
+  };
+  let is_digits = |part: &str| !part.is_empty() && part.chars().all(|c| c.is_ascii_digit());
+  let is_exponent = |part: &str| is_digits(part.strip_prefix(['+', '-']).unwrap_or(part));
+  if !is_digits(whole) || !fraction.is_none_or(is_digits) || !exponent.is_none_or(is_exponent) {
+    return None;
+  }
+  atom.parse().ok()
+}
+
+fn read_atom(atom: String) -> Result<Val, String> {
+  if let Some(num) = read_number(&atom) {
+    return Ok(Val::Num(num));
+  }
+  if let Some(name) = atom.strip_prefix("#\\") {
+    return read_char(name).map(Val::Char).ok_or_else(|| format!("Unknown char {}", atom));
+  }
+
+  let val = match atom.to_lowercase().as_str() {
+    "nil" => Val::nil(),
+    "t" => Val::truth(),
+    "f" => Val::Sym("f".to_string()),
+    _ => if let Some(key) = atom.strip_prefix("#:") {
+      Val::Keyword(key.to_string())
+    } else {
+      Val::Sym(atom)
+    },
+  };
+  Ok(val)
+}
+
+/// Makes the value of a `#v(x y)` or `#record(name (field value)...)`
+/// literal from the parts in its list.
+fn read_literal(tag: &str, parts: &[Val]) -> Result<Val, String> {
+  let literal = match (tag, parts) {
+    ("#v", [Val::Num(x), Val::Num(y)]) => Some(Val::Vec2(Vec2::new(*x, *y))),
+    ("#record", [Val::Sym(name), entries @ ..]) => read_record(name, entries),
+    _ => None,
+  };
+  literal.ok_or_else(|| format!("Malformed {} literal", tag))
+}
+
+/// Reads the fields of a record literal, which are in the `read_object`
+/// format.
+fn read_record(name: &str, entries: &[Val]) -> Option<Val> {
+  let mut fields: Vec<(String, Val)> = vec![];
+  try_read_object(&Val::List(entries.to_vec()), |field, val| {
+    fields.retain(|(name, _)| name != field);
+    fields.push((field.to_string(), val.clone()));
+  }).ok()?;
+  Some(Val::Record(Record { name: name.to_string(), fields }))
+}
+
+struct Reader {
+  tokens: std::vec::IntoIter<Token>,
+}
+
+impl Reader {
+  /// Reads the next expression, or `None` at the end of the tokens.
+  fn read(&mut self) -> Result<Option<Val>, String> {
+    let Some(token) = self.tokens.next() else {
+      return Ok(None);
+    };
+    let val = match token {
+      Token::Open => {
+        let mut list = vec![];
+        loop {
+          match self.tokens.as_slice().first() {
+            Some(Token::Close) => {
+              self.tokens.next();
+              break;
+            },
+            Some(_) => list.extend(self.read()?),
+            None => return Err("Unclosed list".to_string()),
+          }
+        }
+        Val::List(list)
+      },
+      Token::Close => return Err("Unexpected )".to_string()),
+      Token::Quote => self.read_prefixed("quote")?,
+      Token::Comma => self.read_prefixed("comma")?,
+      Token::Str(string) => Val::String(string),
+      Token::Atom(atom) => read_atom(atom)?,
+      Token::Tag(tag) if tag == "#iv" => self.read_ivec2()?,
+      Token::Tag(tag) => match self.read()? {
+        Some(Val::List(parts)) => read_literal(&tag, &parts)?,
+        _ => return Err(format!("Malformed {} literal", tag)),
+      },
+    };
+    Ok(Some(val))
+  }
+
+  /// Reads the list of an `#iv` literal. Its coordinates are read as
+  /// integers, since a float can't hold every one exactly.
+  fn read_ivec2(&mut self) -> Result<Val, String> {
+    let tokens: Vec<Token> = self.tokens.by_ref().take(4).collect();
+    match tokens.as_slice() {
+      [Token::Open, Token::Atom(x), Token::Atom(y), Token::Close] => match (x.parse(), y.parse()) {
+        (Ok(x), Ok(y)) => Ok(Val::IVec2(IVec2::new(x, y))),
+        _ => Err("Malformed #iv literal".to_string()),
+      },
+      _ => Err("Malformed #iv literal".to_string()),
+    }
+  }
+
+  /// Reads the expression after `'` or `,` as `(quote x)` or `(comma x)`.
+  fn read_prefixed(&mut self, name: &str) -> Result<Val, String> {
+    match self.read()? {
+      Some(val) => Ok(Val::List(vec![Val::Sym(name.to_string()), val])),
+      None => Err(format!("Nothing after {}", name)),
+    }
+  }
+}
+
+/// Reads every expression in `source`. On an error, returns the
+/// expressions read before it along with the error.
+pub fn read_all(source: &str) -> (Vec<Val>, Option<String>) {
+  let (tokens, error) = tokenize(source);
+  let mut reader = Reader { tokens: tokens.into_iter() };
+  let mut vals = vec![];
+  loop {
+    match reader.read() {
+      Ok(Some(val)) => vals.push(val),
+      Ok(None) => return (vals, error),
+      Err(error) => return (vals, Some(error)),
+    }
+  }
+}
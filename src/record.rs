@@ -0,0 +1,89 @@
+use crate::{val::Val, exec::State, object::{try_read_object, write_entry}, builtins::{record_new_cb, record_is_cb, record_field_cb}};
+
+/// A value of a type made by `define-record`, tagged with the type's name.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Record {
+  pub name: String,
+  pub fields: Vec<(String, Val)>,
+}
+
+impl Record {
+  pub fn get(&self, field: &str) -> Option<&Val> {
+    self.fields.iter().find(|(name, _)| name == field).map(|(_, val)| val)
+  }
+
+  /// The record in the format `read_object` reads, one entry per field.
+  pub fn to_object(&self) -> Val {
+    Val::List(self.fields.iter().map(|(field, val)| write_entry(field, val)).collect())
+  }
+
+  /// Reads `fields` from an object as `read_object` would. Fields the
+  /// object doesn't have are nil, and keys that aren't fields are ignored.
+  pub fn from_object(name: &str, fields: &[String], object: &Val) -> Result<Record, String> {
+    let mut values = vec![Val::nil(); fields.len()];
+    try_read_object(object, |key, val| {
+      if let Some(i) = fields.iter().position(|field| field == key) {
+        values[i] = val.clone();
+      }
+    })?;
+    Ok(Record {
+      name: name.to_string(),
+      fields: fields.iter().cloned().zip(values).collect(),
+    })
+  }
+}
+
+fn quote(val: Val) -> Val {
+  Val::List(vec![Val::Sym("quote".to_string()), val])
+}
+
+fn sym(name: &str) -> Val {
+  Val::Sym(name.to_string())
+}
+
+impl State {
+  /// Defines a record type in the current scope: `make-<name>` taking the
+  /// fields in order, `<name>?`, and a `<name>-<field>` accessor for each
+  /// field. The definitions call builtins directly, so they keep working
+  /// if scripts shadow the names of other functions.
+  pub fn define_record(&mut self, name: &str, fields: &[String]) {
+    self.records.insert(name.to_string(), fields.to_vec());
+
+    let scope = self.get_var_ref();
+    let field_syms = Val::List(fields.iter().map(|field| sym(field)).collect());
+    let constructor = Val::Lambda(false, scope, vec![
+      sym("lambda"),
+      sym("values"),
+      Val::List(vec![Val::Builtin(false, record_new_cb), quote(sym(name)), quote(field_syms), sym("values")]),
+    ]);
+    self.set_var(&format!("make-{}", name), constructor);
+
+    let predicate = Val::Lambda(false, scope, vec![
+      sym("lambda"),
+      Val::List(vec![sym("value")]),
+      Val::List(vec![Val::Builtin(false, record_is_cb), sym("value"), quote(sym(name))]),
+    ]);
+    self.set_var(&format!("{}?", name), predicate);
+
+    for field in fields {
+      let accessor = Val::Lambda(false, scope, vec![
+        sym("lambda"),
+        Val::List(vec![sym("record")]),
+        Val::List(vec![Val::Builtin(false, record_field_cb), quote(sym(name)), quote(sym(field)), sym("record")]),
+      ]);
+      self.set_var(&format!("{}-{}", name, field), accessor);
+    }
+  }
+
+  /// The fields of a record type, in order.
+  pub fn record_fields(&self, name: &str) -> Option<&Vec<String>> {
+    self.records.get(name)
+  }
+
+  /// Makes a record of a defined type from an object, as `object->record`
+  /// does.
+  pub fn record_from_object(&self, name: &str, object: &Val) -> Result<Val, String> {
+    let fields = self.records.get(name).ok_or_else(|| format!("Unknown record type: {}", name))?;
+    Ok(Val::Record(Record::from_object(name, fields, object)?))
+  }
+}
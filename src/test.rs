@@ -1,4 +1,30 @@
-use crate::{val::*, exec::{eval, State, eval_s}, object::{read_ivec2, read_object, read_string}};
+// tests of groups compiled out are skipped, leaving some helpers unused
+#![cfg_attr(not(all(feature = "math", feature = "strings", feature = "lists", feature = "io", feature = "debug")), allow(unused_imports, dead_code))]
+
+use crate::{val::*, exec::{eval, State, eval_s}, record::Record, cancel::RunOutcome, loader::*, profile::Profile, builtins::{BuiltinGroup, get_builtin_group}, vec2::{Vec2, IVec2}, object::{read_vec2, read_ivec2, read_object, read_string, read_args}};
+
+// answers log messages until the program finishes or waits on something
+// else, returning what was logged
+fn run_logged(s: &mut State) -> Vec<Val> {
+  let mut logged = vec![];
+  s.run();
+  while let Some(message) = s.message_peek() {
+    if message[0] != p("log") {
+      break;
+    }
+    logged.push(message[1].clone());
+    s.message_return(Val::nil());
+    s.run();
+  }
+  logged
+}
+
+// runs a program, answering log messages, and returns what was logged
+// along with the result
+fn run_program_logged(s: &mut State, program: &str) -> (Vec<Val>, Val) {
+  s.set_program(p(program));
+  (run_logged(s), s.result.clone())
+}
 
 #[test]
 fn test_parsing() {
@@ -7,9 +33,27 @@ fn test_parsing() {
   assert_ne!(p("(1)"), p("(1 2)"));
   assert_eq!(p("'(1)"), p("(quote (1))"));
   assert_eq!(eval(p("'(+ 2 2)")), p("(+ 2 2)"));
+
+  // strings are read whole, whatever chars they hold
+  assert_eq!(p("\"a\u{1}b ü\""), Val::String("a\u{1}b ü".to_string()));
+  assert_eq!(p("sym\u{1}é"), Val::Sym("sym\u{1}é".to_string()));
+  assert_eq!(p("(a \"\" \"(b) ; c\")"), Val::List(vec![p("a"), Val::String("".to_string()), Val::String("(b) ; c".to_string())]));
+  assert_eq!(p("(#\\( #\\))"), Val::List(vec![Val::Char('('), Val::Char(')')]));
+  assert_eq!(p_all("(a) ; comment (b)\n(c)"), Ok(vec![p("(a)"), p("(c)")]));
+  assert_eq!(p("(1 -2 3.5 - -x)"), Val::List(vec![p("1"), Val::Num(-2.0), Val::Num(3.5), p("-"), p("-x")]));
+
+  assert_eq!(p("(1 2"), Val::Sym("Error parsing: (1 2".to_string()));
+  assert_eq!(p("\"open"), Val::Sym("Error parsing: \"open".to_string()));
+  assert_eq!(p_all("(a) (b"), Err("Unclosed list".to_string()));
+  assert_eq!(p_all("#\\none"), Err("Unknown char #\\none".to_string()));
+
+  // exponents, and atoms that only start like numbers
+  assert_eq!(p("(1e30 1.5e-3 -2E+4)"), Val::List(vec![Val::Num(1e30), Val::Num(1.5e-3), Val::Num(-2e4)]));
+  assert_eq!(p("(1+ 1x 1e 2e+ 1.)"), Val::List(["1+", "1x", "1e", "2e+", "1."].map(|sym| Val::Sym(sym.to_string())).to_vec()));
 }
 
 #[test]
+#[cfg(feature = "math")]
 fn test_arithmetic() {
   assert_eq!(eval(p("(+ 1 2)")), p("3"));
   assert_eq!(eval(p("(+ 1 2 3)")), p("6"));
@@ -24,12 +68,14 @@ fn test_arithmetic() {
 }
 
 #[test]
+#[cfg(feature = "math")]
 fn test_arithmetic_nested() {
   assert_eq!(eval(p("(* (+ 1 39) (- 53 45))")), p("320"));
   assert_eq!(eval(p("(/ (+ 39 48 72 23 91) 5)")), p("54.6"));
 }
 
 #[test]
+#[cfg(feature = "lists")]
 fn test_car_cdr() {
   assert_eq!(eval(p("(car '(1 2 3))")), p("1"));
   assert_eq!(eval(p("(cdr '(1 2 3))")), p("(2 3)"));
@@ -37,6 +83,7 @@ fn test_car_cdr() {
 }
 
 #[test]
+#[cfg(feature = "math")]
 fn test_variables() {
   let mut state = State::new();
   let s = &mut state;
@@ -62,6 +109,7 @@ fn test_variables() {
 }
 
 #[test]
+#[cfg(feature = "math")]
 fn test_lambda() {
   let mut state = State::new();
   let s = &mut state;
@@ -85,6 +133,96 @@ fn test_lambda() {
 }
 
 #[test]
+#[cfg(all(feature = "math", feature = "lists"))]
+fn test_lambda_list() {
+  let mut state = State::new();
+  let s = &mut state;
+
+  eval_s(&p("(define (f a b . rest) rest)"), s);
+  assert_eq!(eval_s(&p("(f 1 2 3 4)"), s), p("(3 4)"));
+  assert_eq!(eval_s(&p("(f 1 2)"), s), p("()"));
+
+  eval_s(&p("(define (g a #:optional b (c (+ a 10))) (cons a (cons b (cons c '()))))"), s);
+  assert_eq!(eval_s(&p("(g 1 2 3)"), s), p("(1 2 3)"));
+  assert_eq!(eval_s(&p("(g 1 2)"), s), p("(1 2 11)"));
+  assert_eq!(eval_s(&p("(g 1)"), s), p("(1 () 11)"));
+
+  eval_s(&p("(define (h a #:optional (b 5) . rest) (+ a b (car rest)))"), s);
+  assert_eq!(eval_s(&p("(h 1 2 3)"), s), p("6"));
+
+  assert_eq!(eval_s(&p("((lambda x x) 1 2)"), s), p("(1 2)"));
+
+  assert_eq!(eval_s(&p("(g)"), s), Val::Sym("Error: Too few arguments: expected 1, got 0".to_string()));
+  assert_eq!(eval_s(&p("(+ 1 (f 1))"), s), Val::Sym("Error: Too few arguments: expected 2, got 1".to_string()));
+  assert_eq!(eval_s(&p("(f 1 2 3)"), s), p("(3)"));
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "strings", feature = "lists"))]
+fn test_keyword_args() {
+  let mut state = State::new();
+  let s = &mut state;
+
+  assert_eq!(p("#:x"), Val::Keyword("x".to_string()));
+  assert_eq!(eval_s(&p("'(spawn #:x 3)"), s), p("(spawn #:x 3)"));
+
+  eval_s(&p("(define (spawn-unit kind #:key (x 0) (y (+ x 1)) owner) (cons kind (cons x (cons y (cons owner '())))))"), s);
+  assert_eq!(eval_s(&p("(spawn-unit 'tank #:x 3 #:y 4 #:owner 'player)"), s), p("(tank 3 4 player)"));
+  assert_eq!(eval_s(&p("(spawn-unit #:owner 'enemy 'tank)"), s), p("(tank 0 1 enemy)"));
+  assert_eq!(eval_s(&p("(spawn-unit 'tank #:x 5)"), s), p("(tank 5 6 ())"));
+  assert_eq!(eval_s(&p("(spawn-unit 'tank #:z 5)"), s), Val::Sym("Error: Unknown keyword argument: #:z".to_string()));
+
+  // a rest parameter keeps the keyword arguments, unknown ones included
+  eval_s(&p("(define (order unit #:key target . rest) (cons target rest))"), s);
+  assert_eq!(eval_s(&p("(order 'tank 'fast #:target 'base #:speed 2)"), s), p("(base fast #:target base #:speed 2)"));
+
+  // builtins read their keyword arguments with read_args
+  assert_eq!(eval_s(&p("(number->string 255 #:radix 16)"), s), p("\"ff\""));
+  assert_eq!(eval_s(&p("(number->string 3.14159 #:precision 2)"), s), p("\"3.14\""));
+  assert_eq!(eval_s(&p("(string->number \"ff\" #:radix 16)"), s), p("255"));
+
+  let args = read_args(&[p("tank"), p("#:x"), p("3"), p("#:hidden")]);
+  assert_eq!(args.positional, vec![p("tank")]);
+  assert_eq!(args.get("x"), Some(&p("3")));
+  assert_eq!(args.get("hidden"), Some(&p("t")));
+  assert!(!args.has("y"));
+
+  s.message_add("spawn");
+  s.set_program(p("(spawn 'tank #:owner 'player)"));
+  s.run();
+  let (message, args) = s.message_peek_args().unwrap();
+  assert_eq!(message, "spawn");
+  assert_eq!(args.positional, vec![p("tank")]);
+  assert_eq!(args.get("owner"), Some(&p("player")));
+}
+
+#[test]
+#[cfg(feature = "math")]
+fn test_define_body() {
+  let mut state = State::new();
+  let s = &mut state;
+
+  // every body form runs, not just the first
+  s.message_add("log");
+  s.set_program(p("(do (define (f x) (log 'first) (log 'second) (* x 2)) (f 4))"));
+  s.run();
+  assert_eq!(s.message_peek(), Some(vec![p("log"), p("first")]));
+  s.message_return(Val::nil());
+  s.run();
+  assert_eq!(s.message_peek(), Some(vec![p("log"), p("second")]));
+  s.message_return(Val::nil());
+  s.run();
+  assert_eq!(s.result, p("8"));
+
+  eval_s(&p("(define (g x) (define y (+ x 1)) (* y y))"), s);
+  assert_eq!(eval_s(&p("(g 2)"), s), p("9"));
+  eval_s(&p("(define (h) 1 2 3)"), s);
+  assert_eq!(eval_s(&p("h"), s), p("(lambda () 1 2 3)"));
+  assert_eq!(eval_s(&p("(h)"), s), p("3"));
+}
+
+#[test]
+#[cfg(feature = "math")]
 fn test_if() {
   let mut state = State::new();
   let s = &mut state;
@@ -98,6 +236,52 @@ fn test_if() {
   assert_eq!(eval_s(&p("(if (> 2 x) \"yes\" \"no\")"), s), p("\"no\""));
 }
 
+#[test]
+#[cfg(feature = "math")]
+fn test_logic_forms() {
+  let mut state = State::new();
+  let s = &mut state;
+
+  assert_eq!(eval_s(&p("(and)"), s), p("t"));
+  assert_eq!(eval_s(&p("(and 1 2 3)"), s), p("3"));
+  assert_eq!(eval_s(&p("(and (= 1 1) (< 1 2) (+ 1 2))"), s), p("3"));
+  assert_eq!(eval_s(&p("(and (= 1 2) (undefined-fn))"), s), p("()"));
+  assert_eq!(eval_s(&p("(or)"), s), p("()"));
+  assert_eq!(eval_s(&p("(or (= 1 2) (+ 1 1) (undefined-fn))"), s), p("2"));
+  assert_eq!(eval_s(&p("(or (= 1 2) '())"), s), p("()"));
+  assert_eq!(eval_s(&p("(or unbound 4)"), s), p("4"));
+  // unbound symbols in tail position are returned as they are, by every
+  // form alike
+  assert_eq!(eval_s(&p("(and 1 unbound)"), s), p("unbound"));
+  assert_eq!(eval_s(&p("(or () unbound)"), s), p("unbound"));
+  assert_eq!(eval_s(&p("(if 1 unbound 1)"), s), p("unbound"));
+  assert_eq!(eval_s(&p("(if () 1 unbound)"), s), p("unbound"));
+  assert_eq!(eval_s(&p("(when 1 unbound)"), s), p("unbound"));
+  assert_eq!(eval_s(&p("(unless () 1 unbound)"), s), p("unbound"));
+  assert_eq!(eval_s(&p("(case 1 ((1) unbound))"), s), p("unbound"));
+
+  assert_eq!(eval_s(&p("(when (= 1 1) 1 2)"), s), p("2"));
+  assert_eq!(eval_s(&p("(when (= 1 2) 1 2)"), s), p("()"));
+  assert_eq!(eval_s(&p("(unless (= 1 2) 1 (+ 1 2))"), s), p("3"));
+  assert_eq!(eval_s(&p("(unless (= 1 1) 1 2)"), s), p("()"));
+
+  eval_s(&p("(define (kind x) (case x ((1 2 3) 'small) ((tank) 'unit) (else 'other)))"), s);
+  assert_eq!(eval_s(&p("(kind 2)"), s), p("small"));
+  assert_eq!(eval_s(&p("(kind 'tank)"), s), p("unit"));
+  assert_eq!(eval_s(&p("(kind (+ 50 50))"), s), p("other"));
+  assert_eq!(eval_s(&p("(case 5 ((1) 'one))"), s), p("()"));
+
+  // the last form is a tail call, so deep recursion keeps a flat stack
+  eval_s(&p("(define (count n) (and (> n 0) (count (- n 1))))"), s);
+  s.set_program(p("(count 500)"));
+  let mut max_stack = 0;
+  while s.step().is_none() {
+    max_stack = max_stack.max(s.stack.len());
+  }
+  assert_eq!(s.result, p("()"));
+  assert!(max_stack < 5);
+}
+
 #[test]
 fn test_types() {
   assert_eq!(eval(p("(list? ())")), p("t"));
@@ -126,6 +310,7 @@ fn test_types() {
 }
 
 #[test]
+#[cfg(all(feature = "math", feature = "lists"))]
 fn test_recursion() {
   let mut state = State::new();
   let s = &mut state;
@@ -144,6 +329,7 @@ fn test_recursion() {
 }
 
 #[test]
+#[cfg(all(feature = "math", feature = "debug"))]
 pub fn test_collatz() {
   let mut state = State::new();
   let s = &mut state;
@@ -173,9 +359,17 @@ fn test_cond() {
   assert_eq!(eval_s(&p("(m 1)"), s), p("1"));
   assert_eq!(eval_s(&p("(m 2)"), s), p("2"));
   assert_eq!(eval_s(&p("(m 4)"), s), p("3"));
+
+  // cond only needs core builtins
+  let mut core = State::builder().groups(&[]).lib(true).build();
+  eval_s(&p("(define (m x) (cond ((= x 1) 1) ((= x 2) 2) (else 3)))"), &mut core);
+  assert_eq!(eval_s(&p("(m 2)"), &mut core), p("2"));
+  assert_eq!(eval_s(&p("(m 4)"), &mut core), p("3"));
+  assert_eq!(eval_s(&p("(cond ((= 1 2) 1))"), &mut core), p("()"));
 }
 
 #[test]
+#[cfg(feature = "math")]
 fn test_closure() {
   let mut state = State::new();
   let s = &mut state;
@@ -207,6 +401,133 @@ fn test_closure() {
 }
 
 #[test]
+#[cfg(all(feature = "math", feature = "lists"))]
+fn test_continuations() {
+  let mut state = State::new();
+  let s = &mut state;
+
+  assert_eq!(eval_s(&p("(+ 1 (call/cc (lambda (k) (+ 10 (k 5)))))"), s), p("6"));
+  assert_eq!(eval_s(&p("(+ 1 (call/cc (lambda (k) 5)))"), s), p("6"));
+
+  // re-entering after the capturing call returned
+  eval_s(&p("(define (counter) (define x 10) (cons x (cons (call/cc (lambda (k) k)) '())))"), s);
+  eval_s(&p("(define saved (counter))"), s);
+  assert_eq!(eval_s(&p("(car saved)"), s), p("10"));
+  eval_s(&p("((car (cdr saved)) 5)"), s);
+  assert_eq!(eval_s(&p("saved"), s), p("(10 5)"));
+
+  assert_eq!(eval_s(&p("(call/ec (lambda (k) (+ 1 (k 42))))"), s), p("42"));
+  assert_eq!(eval_s(&p("(call/ec (lambda (k) 7))"), s), p("7"));
+  eval_s(&p("(define (search ls k) (if ls (if (> (car ls) 2) (k (car ls)) (search (cdr ls) k)) 'none))"), s);
+  assert_eq!(eval_s(&p("(call/ec (lambda (return) (* 100 (search '(1 2 5 7) return))))"), s), p("5"));
+  assert_eq!(eval_s(&p("(call/ec (lambda (return) (search '(1 2) return)))"), s), p("none"));
+
+  // continuations compare by identity
+  eval_s(&p("(define k1 (call/cc (lambda (k) k)))"), s);
+  assert_eq!(eval_s(&p("(= k1 k1)"), s), p("t"));
+  assert_ne!(eval_s(&p("(call/cc (lambda (k) k))"), s), eval_s(&p("(call/cc (lambda (k) k))"), s));
+
+  // scopes of finished captures are freed again, so loops don't leak
+  eval_s(&p("(define (step n) (define m (* n 2)) (+ m (call/cc (lambda (k) (k 1)))))"), s);
+  assert_eq!(eval_s(&p("(step 1)"), s), p("3"));
+  let usage = s.memory_usage();
+  for _ in 0..50 {
+    eval_s(&p("(step 1)"), s);
+  }
+  assert_eq!(s.memory_usage(), usage);
+
+  eval_s(&p("(define esc (call/ec (lambda (k) k)))"), s);
+  assert_eq!(eval_s(&p("(esc 1)"), s), Val::Sym("Error: Escape used after its call/ec returned".to_string()));
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "lists", feature = "generators"))]
+fn test_generators() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  eval_s(&p("(define (patrol) (yield '(1 2)) (yield '(3 4)) (yield '(5 6)))"), s);
+  eval_s(&p("(define g (generator patrol))"), s);
+  assert_eq!(eval_s(&p("(generator-done? g)"), s), p("()"));
+  assert_eq!(eval_s(&p("(g)"), s), p("(1 2)"));
+  assert_eq!(eval_s(&p("(cons (g) (g))"), s), p("((3 4) 5 6)"));
+  assert_eq!(eval_s(&p("(g)"), s), p("()"));
+  assert_eq!(eval_s(&p("(generator-done? g)"), s), p("t"));
+  assert_eq!(eval_s(&p("(g)"), s), p("()"));
+
+  // closures outlive the call that made the generator, and values can be
+  // sent back in through the yield
+  eval_s(&p("(define (counter n) (generator (lambda () (define (go i) (go (+ i (yield i)))) (go n))))"), s);
+  eval_s(&p("(define c (counter 10))"), s);
+  assert_eq!(eval_s(&p("(c)"), s), p("10"));
+  assert_eq!(eval_s(&p("(c 5)"), s), p("15"));
+  for _ in 0..100 {
+    eval_s(&p("(c 1)"), s);
+  }
+  assert_eq!(eval_s(&p("(c 0)"), s), p("115"));
+  let Val::Generator(id) = eval_s(&p("c"), s) else { panic!("c is not a generator") };
+  assert_eq!(s.generators[&id].stack.len(), 3);
+
+  // finished generators are forgotten and their closures freed
+  eval_s(&p("(define (drain n) (define g (generator (lambda () (yield n) (yield n)))) (g) (g) (g) (generator-done? g))"), s);
+  assert_eq!(eval_s(&p("(drain 1)"), s), p("t"));
+  let usage = s.memory_usage();
+  let generators = s.generators.len();
+  for _ in 0..50 {
+    eval_s(&p("(drain 1)"), s);
+  }
+  assert_eq!(s.memory_usage(), usage);
+  assert_eq!(s.generators.len(), generators);
+
+  // a generator left by an error is done, and its closure freed
+  eval_s(&p("(define (fail n) (define g (generator (lambda () (yield n) (error 'boom)))) (g) (g))"), s);
+  eval_s(&p("(define broken (generator (lambda () (yield 1) (error 'boom))))"), s);
+  eval_s(&p("(broken)"), s);
+  assert_eq!(eval_s(&p("(broken)"), s), Val::Sym("Error: boom".to_string()));
+  assert_eq!(eval_s(&p("(generator-done? broken)"), s), p("t"));
+  eval_s(&p("(fail 1)"), s);
+  let usage = s.memory_usage();
+  for _ in 0..50 {
+    eval_s(&p("(fail 1)"), s);
+  }
+  assert_eq!(s.memory_usage(), usage);
+  assert_eq!(s.generators.len(), generators);
+
+  // as is one closed before it runs to the end
+  eval_s(&p("(define (abandon n) (define g (generator (lambda () (yield n) (yield n)))) (g) (generator-close g))"), s);
+  assert_eq!(eval_s(&p("(abandon 1)"), s), p("t"));
+  let usage = s.memory_usage();
+  for _ in 0..50 {
+    eval_s(&p("(abandon 1)"), s);
+  }
+  assert_eq!(s.memory_usage(), usage);
+  assert_eq!(s.generators.len(), generators);
+  assert_eq!(eval_s(&p("(generator-close g)"), s), p("()"));
+  eval_s(&p("(define self-closing (generator (lambda () (generator-close self-closing))))"), s);
+  assert_eq!(eval_s(&p("(self-closing)"), s), p("()"));
+
+  s.set_program(p("(do (yield 1) (yield (+ 1 1)) (patrol) 'done)"));
+  let yielded: Vec<Val> = s.yields(1000).collect();
+  assert_eq!(yielded, vec![p("1"), p("2"), p("(1 2)"), p("(3 4)"), p("(5 6)")]);
+  assert_eq!(s.result, p("done"));
+
+  // the host can drive a generator, within a step budget
+  let waypoints = eval_s(&p("(generator patrol)"), s);
+  let yielded: Vec<Val> = s.generator_yields(&waypoints, 1000).collect();
+  assert_eq!(yielded, vec![p("(1 2)"), p("(3 4)"), p("(5 6)")]);
+  let Val::Generator(id) = waypoints else { panic!("not a generator") };
+  assert!(s.generator_done(id));
+
+  let endless = eval_s(&p("(generator (lambda () (define (go i) (yield i) (go (+ i 1))) (go 0)))"), s);
+  assert_eq!(s.generator_yields(&endless, 1000).take(3).collect::<Vec<Val>>(), vec![p("0"), p("1"), p("2")]);
+  let stuck = eval_s(&p("(generator (lambda () (loop)))"), s);
+  assert_eq!(s.generator_yields(&stuck, 1000).next(), None);
+  assert_eq!(s.generators.len(), generators + 2);
+}
+
+#[test]
+#[cfg(feature = "math")]
 fn test_message() {
   let mut state = State::new();
   let s = &mut state;
@@ -229,6 +550,7 @@ fn test_message() {
 }
 
 #[test]
+#[cfg(feature = "lists")]
 fn test_message_loop() {
   let mut state = State::new();
   let s = &mut state;
@@ -255,6 +577,41 @@ fn test_message_loop() {
   }
 }
 
+#[test]
+#[cfg(all(feature = "math", feature = "lists"))]
+fn test_message_tail_calls() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  s.message_add("test");
+  eval_s(&p("(define (patrol n) (test 1 n) (cond ((> n 100) 'done) (else (guard (+ n 1)))))"), s);
+  eval_s(&p("(define (guard n) (apply watch (cons n '())))"), s);
+  eval_s(&p("(define-syntax watching (lambda (eval-context body) (eval-context (car body))))"), s);
+  eval_s(&p("(define (watch n) (test 2 n) (watching (patrol n)))"), s);
+  s.set_program(p("(patrol 0)"));
+
+  let mut init_mem = 0;
+  for i in 0..10 {
+    s.run();
+    assert_eq!(s.message_peek(), Some(vec![p("test"), p("1"), Val::Num(i as f32)]));
+    s.message_return(Val::nil());
+    s.run();
+    assert_eq!(s.message_peek(), Some(vec![p("test"), p("2"), Val::Num(i as f32 + 1.0)]));
+    s.message_return(Val::nil());
+
+    //check tail recursion
+    assert!(s.stack.len() <= 3);
+    if i == 0 {
+      init_mem = s.memory_usage();
+    }
+    assert_eq!(s.memory_usage(), init_mem);
+  }
+
+  eval_s(&p("(define (f x) (list? x) x)"), s);
+  assert_eq!(eval_s(&p("(apply f '((a b)))"), s), p("(a b)"));
+}
+
 #[test]
 fn test_read_object() {
   let obj = p("((name test) (type item) (size 1 1) (health 100) (speed 0.1) (range 0) (power 0) (consumes 0) (outputs 0) (requirements 0) (buildtime 0) (very-cool) (description \"This is some complex object\"))");
@@ -267,16 +624,11 @@ fn test_read_object() {
       "type" => assert_eq!(val, &p("item")),
       "size" => {
         assert_eq!(val, &p("(1 1)"));
-        read_ivec2(&val, |x, y| {
-          assert_eq!(x, 1);
-          assert_eq!(y, 1);
-        }, || panic!("Invalid size"));
+        assert_eq!(read_ivec2(val), Some(IVec2::new(1, 1)));
       },
       "health" => {
         assert_eq!(val, &p("100"));
-        read_ivec2(&val, |_, _| {
-          panic!("read_ivec2 should not be called on a single value");
-        }, || ());
+        assert_eq!(read_ivec2(val), None);
       },
       "speed" => assert_eq!(val, &p("0.1")),
       "range" => assert_eq!(val, &p("0")),
@@ -297,6 +649,7 @@ fn test_read_object() {
 }
 
 #[test]
+#[cfg(feature = "math")]
 fn test_memory_management() {
   let mut state = State::new();
   let s = &mut state;
@@ -329,6 +682,7 @@ fn test_memory_management() {
 }
 
 #[test]
+#[cfg(feature = "strings")]
 fn test_string() {
   let mut state = State::new();
   let s = &mut state;
@@ -376,3 +730,1015 @@ fn test_string() {
 
 }
 
+#[test]
+#[cfg(feature = "math")]
+fn test_unwind_protect() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+  s.message_add("log");
+
+  assert_eq!(run_program_logged(s, "(unwind-protect (+ 1 2) (log 'a) (log 'b))"), (vec![p("a"), p("b")], p("3")));
+
+  let (logged, result) = run_program_logged(s, "(unwind-protect (+ 1 (error \"bad\" 'thing)) (log 'cleanup))");
+  assert_eq!(logged, vec![p("cleanup")]);
+  assert_eq!(result, Val::Sym("Error: bad thing".to_string()));
+
+  assert_eq!(
+    run_program_logged(s, "(call/ec (lambda (k) (unwind-protect (unwind-protect (k 5) (log 'inner)) (log 'outer))))"),
+    (vec![p("inner"), p("outer")], p("5")));
+  assert_eq!(
+    run_program_logged(s, "(+ 1 (call/cc (lambda (k) (unwind-protect (k 5) (log 'jumped)))))"),
+    (vec![p("jumped")], p("6")));
+
+  // jumping back into a dynamic-wind body runs its before thunk again
+  eval_s(&p("(define (before) (log 'in))"), s);
+  eval_s(&p("(define (after) (log 'out))"), s);
+  eval_s(&p("(define (saved) (call/cc (lambda (k) k)))"), s);
+  let (logged, result) = run_program_logged(s,
+    "(do (define k (dynamic-wind before saved after)) (if (number? k) k (k 7)))");
+  assert_eq!(logged, vec![p("in"), p("out"), p("in"), p("out")]);
+  assert_eq!(result, p("7"));
+
+  // frames made after many others still tell their ids apart
+  s.marker_count = (1 << 25) - 1;
+  assert_eq!(
+    run_program_logged(s, "(+ 1 (unwind-protect (call/cc (lambda (k) (unwind-protect (k 5) (log 'inner)))) (log 'outer)))"),
+    (vec![p("inner"), p("outer")], p("6")));
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "strings"))]
+fn test_cancel() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+  s.message_add("log");
+  s.message_add("wait");
+
+  assert!(!s.cancel("nothing running"));
+
+  // cancelling while the program waits on a message runs its cleanups
+  s.set_program(p("(unwind-protect (wait) (log 'cleanup))"));
+  assert_eq!(s.run_for(100), RunOutcome::Waiting(vec![p("wait")]));
+  assert!(s.cancel("shutting down"));
+  assert_eq!(s.run_for(100), RunOutcome::Waiting(vec![p("log"), p("cleanup")]));
+  s.message_return(Val::nil());
+  assert_eq!(s.run_for(100), RunOutcome::Cancelled("shutting down".to_string()));
+  assert_eq!(s.result, Val::Sym("Error: Cancelled: shutting down".to_string()));
+
+  s.set_program(p("(+ 1 (catch-cancel (+ 100 (wait)) (lambda (reason) (string-length reason))))"));
+  assert_eq!(s.run_for(100), RunOutcome::Waiting(vec![p("wait")]));
+  s.cancel("abc");
+  assert_eq!(s.run_for(100), RunOutcome::Finished(p("4")));
+
+  // deadlines
+  eval_s(&p("(define (spin) (spin))"), s);
+  s.set_program(p("(spin)"));
+  s.set_deadline(50);
+  assert_eq!(s.run_for(40), RunOutcome::Paused);
+  assert_eq!(s.run_for(40), RunOutcome::Cancelled("Deadline exceeded".to_string()));
+
+  s.set_program(p("(catch-cancel (spin) (lambda (reason) 'stopped))"));
+  s.set_deadline(50);
+  assert_eq!(s.run_for(100), RunOutcome::Finished(p("stopped")));
+
+  s.set_program(p("(+ 1 2)"));
+  s.set_deadline(50);
+  assert_eq!(s.run_for(100), RunOutcome::Finished(p("3")));
+  s.set_program(p("(spin)"));
+  assert_eq!(s.run_for(100), RunOutcome::Paused);
+}
+
+#[test]
+fn test_interrupt_queue() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+  s.message_add("log");
+  s.message_add("wait");
+  s.interrupt_source("on-timer", 1);
+  s.interrupt_source("on-damaged", 5);
+
+  // a waiting program is preempted, and resumes waiting afterwards
+  s.set_program(p("(do (log 'main) (wait) (log 'main-done) 'main-result)"));
+  assert_eq!(run_logged(s), vec![p("main")]);
+  s.queue_interrupt("on-timer", p("(do (log 'timer) (log (current-interrupt)))"));
+  s.queue_interrupt("on-damaged", p("(log 'damaged)"));
+  s.queue_interrupt("on-damaged", p("(log 'damaged-again)"));
+  assert_eq!(run_logged(s), vec![p("damaged"), p("damaged-again"), p("timer"), p("on-timer")]);
+  assert_eq!(s.current_interrupt(), None);
+  assert_eq!(s.message_peek(), Some(vec![p("wait")]));
+  s.message_return(Val::nil());
+  assert_eq!(run_logged(s), vec![p("main-done")]);
+  assert_eq!(s.result, p("main-result"));
+
+  // a higher priority preempts a running interrupt, an equal one waits
+  s.set_program(p("(wait)"));
+  s.run();
+  s.queue_interrupt("on-timer", p("(do (log 'timer-start) (wait) (log 'timer-end))"));
+  s.run();
+  assert_eq!(s.message_peek(), Some(vec![p("log"), p("timer-start")]));
+  s.message_return(Val::nil());
+  s.run();
+  assert_eq!(s.current_interrupt(), Some("on-timer"));
+  s.queue_interrupt("on-timer", p("(log 'timer-2)"));
+  s.queue_interrupt("on-damaged", p("(log 'damaged)"));
+  assert_eq!(run_logged(s), vec![p("damaged")]);
+  assert_eq!(s.message_peek(), Some(vec![p("wait")]));
+  s.message_return(Val::nil());
+  assert_eq!(run_logged(s), vec![p("timer-end"), p("timer-2")]);
+  assert_eq!(s.message_peek(), Some(vec![p("wait")]));
+  s.message_return(p("done"));
+  s.run();
+  assert!(s.finished());
+  assert_eq!(s.result, p("done"));
+
+  // interrupts wait for critical sections, and never clobber the result
+  s.set_program(p("(without-interrupts (log 'a) (wait) (log 'b) 'c)"));
+  s.run();
+  s.message_return(Val::nil());
+  s.run();
+  s.queue_interrupt("on-damaged", p("(log 'damaged)"));
+  s.message_return(Val::nil());
+  assert_eq!(run_logged(s), vec![p("b"), p("damaged")]);
+  assert_eq!(s.result, p("c"));
+  assert_eq!(eval_s(&p("(without-interrupts)"), s), p("()"));
+}
+
+#[test]
+#[cfg(all(feature = "lists", feature = "events"))]
+fn test_events() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+  s.message_add("log");
+  s.message_add("wait");
+
+  // handlers keep the closures they were made in
+  eval_s(&p("(define (watch tag) (on 'unit-died (lambda (u) (log (cons tag u)))))"), s);
+  eval_s(&p("(define first (watch 'first))"), s);
+  eval_s(&p("(define second (watch 'second))"), s);
+  eval_s(&p("(define (stall u) (wait) (log u))"), s);
+  eval_s(&p("(define stalled (on 'unit-died stall))"), s);
+  assert_eq!(s.handlers("unit-died").count(), 3);
+  assert_eq!(s.emit("nobody-listens", vec![]), 0);
+
+  s.set_program(p("(do (wait) 'main)"));
+  s.run();
+  assert_eq!(s.emit("unit-died", vec![p("(tank 1)")]), 3);
+  assert_eq!(s.emit("unit-died", vec![p("(jeep 2)")]), 3);
+  assert_eq!(run_logged(s), vec![p("(first tank 1)"), p("(second tank 1)")]);
+
+  // a handler waiting on the host holds up later handlers of the event
+  assert_eq!(s.message_peek(), Some(vec![p("wait")]));
+  assert_eq!(s.current_interrupt(), Some("unit-died"));
+  s.message_return(Val::nil());
+  assert_eq!(run_logged(s), vec![p("(tank 1)"), p("(first jeep 2)"), p("(second jeep 2)")]);
+  s.message_return(Val::nil());
+  assert_eq!(run_logged(s), vec![p("(jeep 2)")]);
+  assert_eq!(s.current_interrupt(), None);
+  s.message_return(Val::nil());
+  s.run();
+  assert_eq!(s.result, p("main"));
+
+  assert_eq!(eval_s(&p("(off stalled)"), s), p("t"));
+  assert_eq!(eval_s(&p("(off stalled)"), s), p("()"));
+  assert_eq!(eval_s(&p("(off 'unit-died)"), s), p("()"));
+  assert_eq!(s.emit("unit-died", vec![p("(3)")]), 2);
+  assert_eq!(run_logged(s), vec![p("(first 3)"), p("(second 3)")]);
+
+  // closures from the same code are told apart by their subscriptions
+  assert_eq!(eval_s(&p("(off first)"), s), p("t"));
+  assert_eq!(s.emit("unit-died", vec![p("(4)")]), 1);
+  assert_eq!(run_logged(s), vec![p("(second 4)")]);
+
+  // unsubscribed handlers release their closures
+  eval_s(&p("(define (churn tag) (off (on 'churned (lambda () (log tag)))))"), s);
+  assert_eq!(eval_s(&p("(churn 1)"), s), p("t"));
+  let usage = s.memory_usage();
+  for _ in 0..50 {
+    eval_s(&p("(churn 1)"), s);
+  }
+  assert_eq!(s.memory_usage(), usage);
+}
+
+#[test]
+#[cfg(feature = "timers")]
+fn test_timers() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+  s.message_add("log");
+
+  eval_s(&p("(define (schedule tag n) (after n (lambda () (log tag))))"), s);
+  eval_s(&p("(schedule 'late 100)"), s);
+  eval_s(&p("(schedule 'soon 30)"), s);
+  eval_s(&p("(schedule 'also-soon 30)"), s);
+  eval_s(&p("(define patrol (every 20 (lambda () (log 'patrol))))"), s);
+
+  assert_eq!(s.tick(19), 0);
+  assert_eq!(run_logged(s), vec![]);
+  assert_eq!(s.tick(1), 1);
+  assert_eq!(run_logged(s), vec![p("patrol")]);
+  assert_eq!(s.tick(20), 3);
+  assert_eq!(run_logged(s), vec![p("soon"), p("also-soon"), p("patrol")]);
+  assert_eq!(s.ticks(), 40);
+
+  assert_eq!(eval_s(&p("(cancel-timer patrol)"), s), p("t"));
+  assert_eq!(eval_s(&p("(cancel-timer patrol)"), s), p("()"));
+  assert_eq!(s.tick(59), 0);
+  assert_eq!(s.tick(1), 1);
+  assert_eq!(run_logged(s), vec![p("late")]);
+
+  // with nothing scheduled, ticks are skipped over
+  s.tick(1_000_000_000);
+  assert_eq!(s.ticks(), 1_000_000_100);
+  let timer = s.add_timer(5, None, p("(lambda () (log 'host))"));
+  assert_eq!(timer, Val::Timer(4));
+  s.tick(5);
+  assert_eq!(run_logged(s), vec![p("host")]);
+
+  // closures of cancelled and fired timers are released
+  eval_s(&p("(cancel-timer (schedule 'cancelled 5))"), s);
+  eval_s(&p("(schedule 'fired 1)"), s);
+  s.tick(1);
+  assert_eq!(run_logged(s), vec![p("fired")]);
+  let usage = s.memory_usage();
+  for _ in 0..50 {
+    eval_s(&p("(cancel-timer (schedule 'cancelled 5))"), s);
+    eval_s(&p("(schedule 'fired 1)"), s);
+    s.tick(1);
+    assert_eq!(run_logged(s), vec![p("fired")]);
+  }
+  assert_eq!(s.memory_usage(), usage);
+
+  // delays too long to count to are never due, and bad ones are errors
+  eval_s(&p("(define never (after 1e30 (lambda () (log 'never))))"), s);
+  eval_s(&p("(every 1000000000000000000000000000000 (lambda () (log 'never)))"), s);
+  assert_eq!(s.tick(100), 0);
+  assert_eq!(eval_s(&p("(cancel-timer never)"), s), p("t"));
+  assert_eq!(eval_s(&p("(after -1 (lambda () 1))"), s), Val::Sym("Error: after expects a finite number of ticks that isn't negative".to_string()));
+  assert_eq!(eval_s(&p("(every 'soon (lambda () 1))"), s), Val::Sym("Error: every expects a finite number of ticks that isn't negative".to_string()));
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "modules"))]
+fn test_modules() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  let dir = std::env::temp_dir().join(format!("conniver-modules-{}", std::process::id()));
+  std::fs::create_dir_all(&dir).unwrap();
+  std::fs::write(dir.join("combat.cnvr"), "
+    (define loads 'combat)
+    (module combat (provide update damage)
+      (define (helper x) (* x 2))
+      (define (damage x) (helper x))
+      (define (update) 'combat-update))
+  ").unwrap();
+  std::fs::write(dir.join("economy.cnvr"), "
+    (module economy (provide update)
+      (define (update) 'economy-update))
+  ").unwrap();
+  std::fs::write(dir.join("broken.cnvr"), "(module broken (provide missing))").unwrap();
+  std::fs::write(dir.join("loop-a.cnvr"), "(module loop-a (require loop-b))").unwrap();
+  std::fs::write(dir.join("loop-b.cnvr"), "(module loop-b (require loop-a))").unwrap();
+  s.set_loader(FsLoader::new(&dir));
+  s.add_module_path("missing");
+  s.add_module_path("");
+
+  assert_eq!(eval_s(&p("(require combat damage)"), s), p("combat"));
+  assert_eq!(eval_s(&p("(require economy)"), s), p("economy"));
+  assert_eq!(eval_s(&p("(damage 4)"), s), p("8"));
+  assert_eq!(eval_s(&p("(combat/update)"), s), p("combat-update"));
+  assert_eq!(eval_s(&p("(economy/update)"), s), p("economy-update"));
+  assert_eq!(eval_s(&p("update"), s), p("update"));
+  assert_eq!(eval_s(&p("helper"), s), p("helper"));
+  assert_eq!(eval_s(&p("loads"), s), p("loads"));
+
+  // loaded once, and requiring is local to the requiring scope
+  std::fs::write(dir.join("combat.cnvr"), "(module combat (provide update))").unwrap();
+  eval_s(&p("(define (f) (require combat update) (update))"), s);
+  assert_eq!(eval_s(&p("(f)"), s), p("combat-update"));
+  assert_eq!(eval_s(&p("update"), s), p("update"));
+
+  eval_s(&p("(module inline (provide x) (define x 5))"), s);
+  assert_eq!(eval_s(&p("(require inline x)"), s), p("inline"));
+  assert_eq!(eval_s(&p("x"), s), p("5"));
+
+  assert_eq!(eval_s(&p("(require combat helper)"), s), Val::Sym("Error: Module combat does not provide helper".to_string()));
+  assert_eq!(eval_s(&p("(require nowhere)"), s), Val::Sym("Error: Module not found: nowhere".to_string()));
+  assert_eq!(eval_s(&p("(require broken)"), s), Val::Sym("Error: Module broken does not define missing".to_string()));
+  assert_eq!(eval_s(&p("(require loop-a)"), s), Val::Sym("Error: Circular require of module loop-a".to_string()));
+
+  // a module whose body failed is loaded again by the next require
+  std::fs::write(dir.join("flaky.cnvr"), "(module flaky (provide x) (error \"not yet\"))").unwrap();
+  assert_eq!(eval_s(&p("(require flaky x)"), s), Val::Sym("Error: not yet".to_string()));
+  assert_eq!(eval_s(&p("(require flaky x)"), s), Val::Sym("Error: not yet".to_string()));
+  std::fs::write(dir.join("flaky.cnvr"), "(module flaky (provide x) (define x 'ready))").unwrap();
+  assert_eq!(eval_s(&p("(require flaky x)"), s), p("flaky"));
+  assert_eq!(eval_s(&p("x"), s), p("ready"));
+
+  // redefining a module releases the scope of the old one
+  eval_s(&p("(module inline (provide x) (define x 5))"), s);
+  let usage = s.memory_usage();
+  for _ in 0..50 {
+    eval_s(&p("(module inline (provide x) (define x 5))"), s);
+  }
+  assert_eq!(s.memory_usage(), usage);
+
+  std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "io", feature = "modules"))]
+fn test_script_loaders() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  let mut memory = MemoryLoader::new();
+  memory.insert("mods/ai.cnvr", "(module ai (provide think) (define (think) 'hmm))");
+  memory.insert("setup.cnvr", "(define loaded-setup 'yes)");
+  s.set_loader(memory);
+  s.add_module_path("mods");
+  assert_eq!(eval_s(&p("(require ai think)"), s), p("ai"));
+  assert_eq!(eval_s(&p("(think)"), s), p("hmm"));
+  eval_s(&p("(load \"./setup.cnvr\")"), s);
+  assert_eq!(eval_s(&p("loaded-setup"), s), p("yes"));
+  assert_eq!(eval_s(&p("(load \"other.cnvr\")"), s), Val::Sym("Error: Script not found: other.cnvr".to_string()));
+  let mut broken = MemoryLoader::new();
+  broken.insert("broken.cnvr", "(define x 1) (oops");
+  broken.insert("mods/broken.cnvr", "(module broken (provide x) #\\none)");
+  s.set_loader(broken);
+  assert_eq!(eval_s(&p("(load \"broken.cnvr\")"), s), Val::Sym("Error: Could not parse broken.cnvr: Unclosed list".to_string()));
+  assert_eq!(eval_s(&p("(require broken x)"), s), Val::Sym("Error: Could not parse module broken: Unknown char #\\none".to_string()));
+  assert_eq!(s.load_lib_from("broken.cnvr"), Err(LoadError::Invalid("broken.cnvr: Unclosed list".to_string())));
+
+  // a tar archive, as made by `tar -cf`
+  fn tar_entry(name: &str, contents: &str) -> Vec<u8> {
+    let mut header = vec![0u8; 512];
+    header[..name.len()].copy_from_slice(name.as_bytes());
+    let size = format!("{:011o}\0", contents.len());
+    header[124..136].copy_from_slice(size.as_bytes());
+    header[156] = b'0';
+    header[257..263].copy_from_slice(b"ustar\0");
+    let mut entry = header;
+    entry.extend(contents.as_bytes());
+    entry.resize(entry.len().div_ceil(512) * 512, 0);
+    entry
+  }
+  let mut tar = tar_entry("pack/units.cnvr", "(module units (provide hp) (define hp 30))");
+  tar.extend(tar_entry("pack/readme.txt", "not a script"));
+  tar.extend(vec![0u8; 1024]);
+  let archive = ArchiveLoader::from_tar(&tar).unwrap();
+  assert_eq!(archive.read("pack/readme.txt"), Ok("not a script".to_string()));
+  s.set_loader(archive);
+  s.add_module_path("pack");
+  assert_eq!(eval_s(&p("(require units hp)"), s), p("units"));
+  assert_eq!(eval_s(&p("hp"), s), p("30"));
+  assert!(ArchiveLoader::from_tar(&tar[..530]).is_err());
+
+  // a zip archive with (name, compression method, data, size) entries
+  fn zip(entries: &[(&str, u16, &[u8], usize)]) -> Vec<u8> {
+    let (mut bytes, mut directory) = (vec![], vec![]);
+    for (name, method, data, size) in entries {
+      let fields = |bytes: &mut Vec<u8>| {
+        bytes.extend([20, 0, 0, 0]);
+        bytes.extend(method.to_le_bytes());
+        bytes.extend([0; 8]);
+        bytes.extend((data.len() as u32).to_le_bytes());
+        bytes.extend((*size as u32).to_le_bytes());
+        bytes.extend((name.len() as u16).to_le_bytes());
+      };
+      directory.extend(0x02014b50u32.to_le_bytes());
+      directory.extend([20, 0]);
+      fields(&mut directory);
+      directory.extend([0; 12]);
+      directory.extend((bytes.len() as u32).to_le_bytes());
+      directory.extend(name.as_bytes());
+      bytes.extend(0x04034b50u32.to_le_bytes());
+      fields(&mut bytes);
+      bytes.extend([0, 0]);
+      bytes.extend(name.as_bytes());
+      bytes.extend(*data);
+    }
+    let offset = bytes.len() as u32;
+    bytes.extend(&directory);
+    bytes.extend(0x06054b50u32.to_le_bytes());
+    bytes.extend([0; 4]);
+    bytes.extend([entries.len() as u8, 0, entries.len() as u8, 0]);
+    bytes.extend((directory.len() as u32).to_le_bytes());
+    bytes.extend(offset.to_le_bytes());
+    bytes.extend([0, 0]);
+    bytes
+  }
+  // deflated with fixed codes, then dynamic ones
+  let units: &[u8] = &[211, 200, 205, 79, 41, 205, 73, 85, 40, 205, 203, 44, 41, 86, 208, 40, 40, 202, 47, 203, 76, 73, 85, 200, 40, 208, 84, 208, 72, 73, 77, 203, 204, 3, 177, 21, 140, 13, 52, 53, 1];
+  let lore: &[u8] = &[93, 138, 209, 13, 195, 32, 12, 68, 87, 57, 241, 69, 102, 232, 52, 168, 118, 82, 36, 138, 19, 32, 237, 88, 89, 32, 127, 44, 22, 35, 164, 180, 137, 127, 124, 239, 238, 217, 183, 208, 26, 24, 65, 18, 195, 206, 73, 62, 158, 148, 124, 228, 60, 192, 18, 143, 154, 58, 194, 46, 171, 20, 149, 76, 121, 49, 138, 124, 57, 33, 23, 23, 41, 27, 252, 85, 163, 11, 225, 218, 116, 9, 110, 114, 62, 234, 160, 128, 155, 254, 219, 218, 71, 51, 122, 58, 61, 51, 232, 225, 129, 186, 199, 186, 61, 133, 248, 0];
+  let pack = zip(&[
+    ("zipped/", 0, b"", 0),
+    ("zipped/units.cnvr", 8, units, 42),
+    ("zipped/lore.cnvr", 8, lore, 184),
+    ("zipped/setup.cnvr", 0, b"(define zipped-setup 'yes)", 26),
+  ]);
+  let archive = ArchiveLoader::from_zip(&pack).unwrap();
+  assert_eq!(archive.read("zipped/units.cnvr"), Ok("(module units (provide hp) (define hp 30))".to_string()));
+  assert!(archive.read("zipped/lore.cnvr").unwrap().ends_with("\"again and again the tower\")))) ; ünïcode"));
+  s.set_loader(archive);
+  eval_s(&p("(load \"zipped/setup.cnvr\")"), s);
+  assert_eq!(eval_s(&p("zipped-setup"), s), p("yes"));
+  // entries can't inflate past their size, or use other compression
+  assert!(ArchiveLoader::from_zip(&zip(&[("units.cnvr", 8, units, 41)])).is_err());
+  assert!(ArchiveLoader::from_zip(&zip(&[("units.cnvr", 8, &units[..20], 42)])).is_err());
+  assert!(ArchiveLoader::from_zip(&zip(&[("units.cnvr", 12, units, 42)])).is_err());
+  assert!(ArchiveLoader::from_zip(&pack[..pack.len() - 30]).is_err());
+
+  s.set_loader(DenyLoader);
+  assert_eq!(eval_s(&p("(load \"setup.cnvr\")"), s), Val::Sym("Error: Script loading denied: setup.cnvr".to_string()));
+  assert_eq!(eval_s(&p("(require economy)"), s), Val::Sym("Error: Script loading denied: mods/economy.cnvr".to_string()));
+  // the bundled library doesn't need the loader
+  let mut sandboxed = State::new();
+  sandboxed.set_loader(DenyLoader);
+  sandboxed.load_lib();
+  assert_eq!(eval_s(&p("(even? 2)"), &mut sandboxed), p("t"));
+
+  let fs = FsLoader::new("cnvr");
+  assert!(fs.read("lib.cnvr").is_ok());
+  assert_eq!(fs.read("../Cargo.toml"), Err(LoadError::Denied("../Cargo.toml".to_string())));
+  assert_eq!(fs.read("/etc/passwd"), Err(LoadError::Denied("/etc/passwd".to_string())));
+  // nor can a symlink inside the root
+  #[cfg(unix)]
+  {
+    let root = std::env::temp_dir().join(format!("conniver-loader-{}", std::process::id()));
+    std::fs::create_dir_all(&root).unwrap();
+    std::fs::write(root.join("inside.cnvr"), "'inside").unwrap();
+    let _ = std::fs::remove_file(root.join("outside.cnvr"));
+    std::os::unix::fs::symlink(std::fs::canonicalize("Cargo.toml").unwrap(), root.join("outside.cnvr")).unwrap();
+    let fs = FsLoader::new(&root);
+    assert_eq!(fs.read("inside.cnvr"), Ok("'inside".to_string()));
+    assert_eq!(fs.read("outside.cnvr"), Err(LoadError::Denied("outside.cnvr".to_string())));
+    std::fs::remove_dir_all(&root).unwrap();
+  }
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "strings", feature = "io", feature = "modules"))]
+fn test_profiles() {
+  fn double_cb(args: Vec<Val>, state: &mut State) {
+    match args.first() {
+      Some(Val::Num(num)) => state.return_stackframe(Val::Num(num * 2.0)),
+      _ => state.return_stackframe(Val::nil()),
+    }
+  }
+
+  let profile = Profile::sandboxed()
+    .allow_message("move")
+    .allow_host_function("double");
+  let mut state = State::with_profile(profile);
+  let s = &mut state;
+  s.load_lib();
+
+  assert!(s.message_add("move"));
+  assert!(!s.message_add("quit-game"));
+  assert!(s.host_function_add("double", double_cb));
+  assert!(!s.host_function_add("delete-save", double_cb));
+
+  assert_eq!(eval_s(&p("(double (+ 1 2))"), s), p("6"));
+  assert_eq!(eval_s(&p("(string-length \"abc\")"), s), p("3"));
+  s.set_program(p("(move 1 2)"));
+  s.run();
+  assert_eq!(s.message_peek(), Some(vec![p("move"), p("1"), p("2")]));
+
+  assert!(s.is_denied("load"));
+  let denied = |name: &str| Val::Sym(format!("Error: Capability denied: {}", name));
+  assert_eq!(eval_s(&p("(load \"cnvr/lib.cnvr\")"), s), denied("load"));
+  assert_eq!(eval_s(&p("(+ 1 (set-program '(loop)))"), s), denied("set-program"));
+  assert_eq!(eval_s(&p("(quit-game)"), s), denied("quit-game"));
+  assert_eq!(eval_s(&p("(delete-save 1)"), s), denied("delete-save"));
+  assert!(s.back_stack.is_empty());
+
+  // scripts may still define what they were denied for themselves
+  eval_s(&p("(define (load x) x)"), s);
+  assert_eq!(eval_s(&p("(load 5)"), s), p("5"));
+  assert_eq!(eval_s(&p("(require anything)"), s), Val::Sym("Error: Script loading denied: anything.cnvr".to_string()));
+
+  let mut trusted = State::new();
+  assert_eq!(trusted.profile(), &Profile::trusted());
+  assert!(trusted.message_add("quit-game"));
+  assert!(!trusted.is_denied("load"));
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "strings", feature = "lists", feature = "io", feature = "debug", feature = "host", feature = "generators", feature = "events", feature = "timers", feature = "modules"))]
+fn test_builder() {
+  let mut config = State::builder()
+    .groups(&[BuiltinGroup::Math])
+    .lib(true)
+    .build();
+  let s = &mut config;
+  assert_eq!(s.profile().groups, vec![BuiltinGroup::Core, BuiltinGroup::Math]);
+  assert_eq!(eval_s(&p("(if (even? 4) (* 2 21) 0)"), s), p("42"));
+  assert_eq!(eval_s(&p("(string-length \"abc\")"), s), Val::Sym("Error: Capability denied: string-length".to_string()));
+  assert_eq!(eval_s(&p("(car '(1 2))"), s), Val::Sym("Error: Capability denied: car".to_string()));
+  assert_eq!(eval_s(&p("(load \"cnvr/lib.cnvr\")"), s), Val::Sym("Error: Capability denied: load".to_string()));
+
+  // the loader only replaces the standard library when asked to
+  let mut memory = MemoryLoader::new();
+  memory.insert("cnvr/lib.cnvr", "(define (from-loader) 'yes)");
+  let mut bundled = State::builder()
+    .loader(memory.clone())
+    .lib(true)
+    .build();
+  assert_eq!(eval_s(&p("(from-loader)"), &mut bundled), p("(from-loader)"));
+  assert_eq!(eval_s(&p("(map (lambda (x) (* x 2)) '(1 2))"), &mut bundled), p("(2 4)"));
+
+  let mut full = State::builder()
+    .without_group(BuiltinGroup::Host)
+    .without_group(BuiltinGroup::Core)
+    .loader(memory)
+    .lib_path("cnvr/lib.cnvr")
+    .build();
+  let s = &mut full;
+  assert_eq!(eval_s(&p("(from-loader)"), s), p("yes"));
+  assert_eq!(eval_s(&p("(print 'debug (+ 1 2))"), s), p("3"));
+  assert!(s.is_denied("set-program"));
+  assert!(!s.is_denied("load"));
+}
+
+#[test]
+fn test_builtin_groups() {
+  let s = State::new();
+  for group in BuiltinGroup::ALL.into_iter().filter(|group| !group.is_enabled()) {
+    // builtins of groups compiled out are denied, not unbound
+    assert!(get_builtin_group(group).is_empty());
+    for name in group.names() {
+      assert!(s.is_denied(name), "{} is not denied", name);
+    }
+  }
+}
+
+#[test]
+fn test_builtin_group_names() {
+  // the names kept for compiled out groups match what the register
+  // functions install, for every group compiled in
+  for group in BuiltinGroup::ALL.into_iter().filter(|group| group.is_enabled() && *group != BuiltinGroup::Core) {
+    let mut registered: Vec<String> = get_builtin_group(group).into_keys().collect();
+    registered.sort();
+    let mut listed: Vec<String> = group.names().iter().map(|name| name.to_string()).collect();
+    listed.sort();
+    assert_eq!(registered, listed, "names of {:?} don't match its register function", group);
+  }
+}
+
+#[test]
+#[cfg(feature = "strings")]
+fn test_strings() {
+  let mut state = State::new();
+  let s = &mut state;
+
+  assert_eq!(eval_s(&p("(string-head \"élan\")"), s), p("\"é\""));
+  assert_eq!(eval_s(&p("(string-tail \"élan\")"), s), p("\"lan\""));
+  assert_eq!(eval_s(&p("(string-head \"\")"), s), p("\"\""));
+  assert_eq!(eval_s(&p("(string-length \"日本語\")"), s), p("3"));
+
+  assert_eq!(eval_s(&p("(substring \"héllo wörld\" 6 9)"), s), p("\"wör\""));
+  assert_eq!(eval_s(&p("(substring \"héllo\" 1)"), s), p("\"éllo\""));
+  assert_eq!(eval_s(&p("(substring \"héllo\" 4 100)"), s), p("\"o\""));
+  assert_eq!(eval_s(&p("(string-index \"日本語の本\" \"本\")"), s), p("1"));
+  assert_eq!(eval_s(&p("(string-index \"日本語の本\" \"本\" 2)"), s), p("4"));
+  assert_eq!(eval_s(&p("(string-index \"日本語\" \"x\")"), s), p("()"));
+  assert_eq!(eval_s(&p("(string-index \"ab\" \"\" 5)"), s), p("()"));
+
+  assert_eq!(eval_s(&p("(string-split \"a,b,,c\" \",\")"), s), p("(\"a\" \"b\" \"\" \"c\")"));
+  assert_eq!(eval_s(&p("(string-split \"  hello   there \")"), s), p("(\"hello\" \"there\")"));
+  assert_eq!(eval_s(&p("(string-join (string-split \"a b c\") \"-\")"), s), p("\"a-b-c\""));
+  assert_eq!(eval_s(&p("(string-upcase \"straße\")"), s), p("\"STRASSE\""));
+  assert_eq!(eval_s(&p("(string-downcase \"ÉCOLE\")"), s), p("\"école\""));
+  assert_eq!(eval_s(&p("(string-trim \"  hi \n\")"), s), p("\"hi\""));
+  assert_eq!(eval_s(&p("(string-replace \"a-b-c\" \"-\" \"→\")"), s), p("\"a→b→c\""));
+  assert_eq!(eval_s(&p("(string-prefix? \"über alles\" \"über\")"), s), p("t"));
+  assert_eq!(eval_s(&p("(string-prefix? \"über\" \"alles\")"), s), p("()"));
+  assert_eq!(eval_s(&p("(string-suffix? \"über alles\" \"alles\")"), s), p("t"));
+  assert_eq!(eval_s(&p("(string->list \"añb\")"), s), p("(#\\a #\\ñ #\\b)"));
+  assert_eq!(eval_s(&p("(string-ref \"añb\" 1)"), s), Val::Char('ñ'));
+  assert_eq!(eval_s(&p("(string-ref \"añb\" 3)"), s), p("()"));
+
+  // non-ASCII text survives the reader, at the end of strings and symbols too
+  assert_eq!(p("\"日本語\""), Val::String("日本語".to_string()));
+  eval_s(&p("(define café '☕)"), s);
+  assert_eq!(eval_s(&p("café"), s), Val::Sym("☕".to_string()));
+}
+
+#[test]
+#[cfg(all(feature = "strings", feature = "lists"))]
+fn test_chars() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  assert_eq!(p("#\\a"), Val::Char('a'));
+  assert_eq!(p("(#\\space #\\newline #\\é)"), Val::List(vec![Val::Char(' '), Val::Char('\n'), Val::Char('é')]));
+  assert_eq!(Val::Char(' ').to_string(), "#\\space");
+  assert_eq!(Val::Char('x').to_string(), "#\\x");
+  assert_eq!(p("#\\bogus"), Val::Sym("Error parsing: #\\bogus".to_string()));
+
+  assert_eq!(eval_s(&p("(char->integer #\\A)"), s), p("65"));
+  assert_eq!(eval_s(&p("(integer->char 955)"), s), Val::Char('λ'));
+  assert_eq!(eval_s(&p("(integer->char -1)"), s), Val::Sym("Error: integer->char expects a Unicode scalar value".to_string()));
+  assert_eq!(eval_s(&p("(char-alphabetic? #\\ж)"), s), p("t"));
+  assert_eq!(eval_s(&p("(char-alphabetic? #\\3)"), s), p("()"));
+  assert_eq!(eval_s(&p("(char-numeric? #\\3)"), s), p("t"));
+  assert_eq!(eval_s(&p("(char-whitespace? #\\tab)"), s), p("t"));
+  assert_eq!(eval_s(&p("(char-upcase #\\ä)"), s), Val::Char('Ä'));
+  assert_eq!(eval_s(&p("(char-upcase #\\ß)"), s), Val::Char('ß'));
+  assert_eq!(eval_s(&p("(char-downcase #\\Q)"), s), Val::Char('q'));
+  assert_eq!(eval_s(&p("(char-upcase \"a\")"), s), Val::Sym("Error: char-upcase expects a char".to_string()));
+  assert_eq!(eval_s(&p("(char-alphabetic? \"a\")"), s), Val::Sym("Error: char-alphabetic? expects a char".to_string()));
+  assert_eq!(eval_s(&p("(char-numeric? 3)"), s), Val::Sym("Error: char-numeric? expects a char".to_string()));
+  assert_eq!(eval_s(&p("(char-whitespace?)"), s), Val::Sym("Error: char-whitespace? expects a char".to_string()));
+  assert_eq!(eval_s(&p("(char? (string-ref \"abc\" 0))"), s), p("t"));
+  assert_eq!(eval_s(&p("(= #\\a (string-ref \"abc\" 0))"), s), p("t"));
+
+  // a name generator: capitalize and join syllables
+  assert_eq!(eval_s(&p("(string #\\K #\\a #\\i)"), s), p("\"Kai\""));
+  eval_s(&p("(define (capitalize word) (define chars (string->list word)) (list->string (cons (char-upcase (car chars)) (cdr chars))))"), s);
+  assert_eq!(eval_s(&p("(capitalize \"élodie\")"), s), p("\"Élodie\""));
+  assert_eq!(eval_s(&p("(string-cons \"ab\" #\\c)"), s), p("\"abc\""));
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "strings"))]
+fn test_conversions() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  assert_eq!(eval_s(&p("(number->string 42)"), s), p("\"42\""));
+  assert_eq!(eval_s(&p("(number->string 2.5)"), s), p("\"2.5\""));
+  assert_eq!(eval_s(&p("(number->string 255 16)"), s), p("\"ff\""));
+  assert_eq!(eval_s(&p("(number->string -5 2)"), s), p("\"-101\""));
+  assert_eq!(eval_s(&p("(number->string 3.14159 10 2)"), s), p("\"3.14\""));
+  assert_eq!(eval_s(&p("(string-length (number->string 1 10 1000000000))"), s), p("102"));
+  assert_eq!(eval_s(&p("(number->string 1.5 2)"), s), Val::Sym("Error: number->string can only write integers in other radixes".to_string()));
+  assert_eq!(eval_s(&p("(number->string 10 99)"), s), Val::Sym("Error: number->string expects a radix from 2 to 36".to_string()));
+
+  assert_eq!(eval_s(&p("(string->number \"12.5\")"), s), p("12.5"));
+  assert_eq!(eval_s(&p("(string->number \" -3 \")"), s), p("-3"));
+  assert_eq!(eval_s(&p("(string->number \"ff\" 16)"), s), p("255"));
+  assert_eq!(eval_s(&p("(string->number \"12abc\")"), s), Val::Sym("Error: Not a number: 12abc".to_string()));
+  // a failed conversion is a value, not an abort
+  assert_eq!(eval_s(&p("(number? (string->number \"nope\"))"), s), p("()"));
+
+  assert_eq!(eval_s(&p("(symbol->string 'tank)"), s), p("\"tank\""));
+  assert_eq!(eval_s(&p("(string->symbol \"unit-died\")"), s), p("unit-died"));
+  assert_eq!(eval_s(&p("(= (string->symbol (symbol->string 'abc)) 'abc)"), s), p("t"));
+  assert_eq!(eval_s(&p("(symbol->string 5)"), s), Val::Sym("Error: symbol->string expects a symbol".to_string()));
+
+  // a chat command parser
+  assert_eq!(eval_s(&p("(read-from-string \"(move 3 4)\")"), s), p("(move 3 4)"));
+  assert_eq!(eval_s(&p("(eval (read-from-string \"(+ 1 2)\"))"), s), p("3"));
+  assert_eq!(eval_s(&p("(read-from-string \"\")"), s), Val::Sym("Error: Could not read: ".to_string()));
+  assert_eq!(eval_s(&p("(read-from-string \"(unclosed\")"), s), Val::Sym("Error: Could not read: (unclosed".to_string()));
+}
+
+#[test]
+#[cfg(feature = "strings")]
+fn test_format() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  assert_eq!(eval_s(&p("(format \"~a has ~d hp\" \"Tank\" 30)"), s), p("\"Tank has 30 hp\""));
+  assert_eq!(eval_s(&p("(format \"~s and ~a\" \"quoted\" 'sym)"), s), Val::String("\"quoted\" and sym".to_string()));
+  assert_eq!(eval_s(&p("(format \"[~6a][~6@a]\" 'ab 'cd)"), s), p("\"[ab    ][    cd]\""));
+  assert_eq!(eval_s(&p("(format \"~5,'0d|~@d|~:d\" 42 7 1234567)"), s), p("\"00042|+7|1,234,567\""));
+  assert_eq!(eval_s(&p("(format \"~x ~b ~o\" 255 5 8)"), s), p("\"ff 101 10\""));
+  assert_eq!(eval_s(&p("(format \"~,2f|~8,3f\" 3.14159 2.5)"), s), p("\"3.14|   2.500\""));
+  assert_eq!(eval_s(&p("(format \"~{~a~^, ~}\" '(1 2 3))"), s), p("\"1, 2, 3\""));
+  assert_eq!(eval_s(&p("(format \"~{<~a=~a>~}\" '(x 1 y 2))"), s), p("\"<x=1><y=2>\""));
+  assert_eq!(eval_s(&p("(format \"100~~~%\")"), s), Val::String("100~\n".to_string()));
+  // as in Common Lisp, arguments left over are ignored
+  assert_eq!(eval_s(&p("(format \"~a\" 1 2)"), s), p("\"1\""));
+
+  // without directives, format still joins its arguments
+  assert_eq!(eval_s(&p("(format \"hp: \" 5 'x)"), s), p("\"hp: 5x\""));
+  assert_eq!(eval_s(&p("(format 1 2)"), s), p("\"12\""));
+
+  // parameters are capped, so scripts can't fill the host's memory
+  let max = crate::format::MAX_PARAM as usize;
+  assert_eq!(eval_s(&p("(string-length (format \"~1000000000a\" 'x))"), s), Val::Num(max as f32));
+  assert_eq!(eval_s(&p("(string-length (format \"~1000000000%\"))"), s), Val::Num(max as f32));
+  assert_eq!(eval_s(&p("(string-length (format \"~,1000000000f\" 1))"), s), Val::Num((max + 2) as f32));
+
+  assert_eq!(eval_s(&p("(format \"~a ~a\" 1)"), s), Val::Sym("Error: Not enough arguments for format".to_string()));
+  assert_eq!(eval_s(&p("(format \"~q\" 1)"), s), Val::Sym("Error: Unknown format directive ~q".to_string()));
+  assert_eq!(eval_s(&p("(format \"~{~a\" '(1))"), s), Val::Sym("Error: ~{ without a matching ~}".to_string()));
+
+  assert_eq!(crate::format("~a: ~,1f%", &[p("Armor"), p("12.34")]), Ok("Armor: 12.3%".to_string()));
+  assert_eq!(crate::format("~{~a~^ ~}", &[p("(a b)"), p("unused")]), Ok("a b".to_string()));
+  assert!(crate::format("~d", &[]).is_err());
+}
+
+#[test]
+#[cfg(feature = "math")]
+fn test_math() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  assert_eq!(eval_s(&p("(+ 1 2 3)"), s), p("6"));
+  assert_eq!(eval_s(&p("(- 5)"), s), p("-5"));
+  assert_eq!(eval_s(&p("(- 10 3 2)"), s), p("5"));
+  assert_eq!(eval_s(&p("(< 1 2 3)"), s), p("t"));
+  assert_eq!(eval_s(&p("(< 1 3 2)"), s), p("()"));
+  // non-numbers are type errors, not silently skipped
+  assert_eq!(eval_s(&p("(+ 1 'a 2)"), s), Val::Sym("Error: + expects numbers, got a".to_string()));
+  assert_eq!(eval_s(&p("(< 1 ())"), s), Val::Sym("Error: < expects numbers, got ()".to_string()));
+
+  assert_eq!(eval_s(&p("(sqrt 16)"), s), p("4"));
+  assert_eq!(eval_s(&p("(sqrt -1)"), s), Val::Sym("Error: sqrt of negative number -1".to_string()));
+  assert_eq!(eval_s(&p("(expt 2 10)"), s), p("1024"));
+  assert_eq!(eval_s(&p("(sin 0)"), s), p("0"));
+  assert_eq!(eval_s(&p("(cos 0)"), s), p("1"));
+  assert_eq!(eval_s(&p("(< 1.57 (atan2 1 0) 1.58)"), s), p("t"));
+  assert_eq!(eval_s(&p("(floor -2.5)"), s), p("-3"));
+  assert_eq!(eval_s(&p("(ceiling 2.1)"), s), p("3"));
+  assert_eq!(eval_s(&p("(round 2.5)"), s), p("3"));
+  assert_eq!(eval_s(&p("(truncate -2.7)"), s), p("-2"));
+  assert_eq!(eval_s(&p("(abs -4)"), s), p("4"));
+  assert_eq!(eval_s(&p("(min 3 1 2)"), s), p("1"));
+  assert_eq!(eval_s(&p("(max 3 1 2)"), s), p("3"));
+  assert_eq!(eval_s(&p("(max)"), s), Val::Sym("Error: max expects at least one number".to_string()));
+  assert_eq!(eval_s(&p("(clamp 12 0 10)"), s), p("10"));
+  assert_eq!(eval_s(&p("(clamp 5 10 0)"), s), Val::Sym("Error: clamp bounds out of order: 10 > 0".to_string()));
+  assert_eq!(eval_s(&p("(lerp 10 20 0.25)"), s), p("12.5"));
+  assert_eq!(eval_s(&p("(abs \"4\")"), s), Val::Sym("Error: abs expects numbers, got \"4\"".to_string()));
+  assert_eq!(eval_s(&p("(expt 2)"), s), Val::Sym("Error: expt expects 2 arguments, got 1".to_string()));
+}
+
+#[test]
+#[cfg(feature = "math")]
+fn test_random() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+  s.seed_rng(42);
+
+  let rolls = p("(list (random) (random 10) (random-int 6) (random-int 10 20) (random-choice '(a b c)) (shuffle '(1 2 3 4 5)))");
+  let first = eval_s(&rolls, s);
+  // the same seed replays the same rolls
+  s.seed_rng(42);
+  assert_eq!(eval_s(&rolls, s), first);
+  assert_eq!(s.rng_seed(), 42);
+  // and so does a snapshot of the state
+  let mut snapshot = s.clone();
+  assert_eq!(eval_s(&rolls, &mut snapshot), eval_s(&rolls, s));
+  s.seed_rng(7);
+  assert_ne!(eval_s(&rolls, s), first);
+
+  for _ in 0..50 {
+    assert_eq!(eval_s(&p("(do (define n (random)) (and (>= n 0) (< n 1)))"), s), p("t"));
+    assert_eq!(eval_s(&p("(do (define n (random-int 3 5)) (and (>= n 3) (< n 5) (= n (floor n))))"), s), p("t"));
+    assert_eq!(eval_s(&p("(do (define c (random-choice '(a b))) (or (= c 'a) (= c 'b)))"), s), p("t"));
+  }
+  let shuffled = eval_s(&p("(shuffle '(1 2 3 4 5 6 7 8))"), s);
+  let Val::List(mut items) = shuffled else { panic!("shuffle should return a list") };
+  items.sort_by_key(|item| item.to_string());
+  assert_eq!(Val::List(items), p("(1 2 3 4 5 6 7 8)"));
+
+  assert_eq!(eval_s(&p("(random-int 5 5)"), s), Val::Sym("Error: random-int has an empty range from 5 to 5".to_string()));
+  assert_eq!(eval_s(&p("(random-int 2.5)"), s), Val::Sym("Error: random-int expects integer bounds".to_string()));
+  assert_eq!(eval_s(&p("(random-choice ())"), s), Val::Sym("Error: random-choice of an empty list".to_string()));
+  assert_eq!(eval_s(&p("(shuffle 3)"), s), Val::Sym("Error: shuffle expects a list".to_string()));
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "strings"))]
+fn test_vec2() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  assert_eq!(p("#v(1.5 2)"), Val::Vec2(Vec2::new(1.5, 2.0)));
+  assert_eq!(p("#iv(3 -4)"), Val::IVec2(IVec2::new(3, -4)));
+  assert_eq!(p("(a #v(1 2) b)"), Val::List(vec![p("a"), Val::Vec2(Vec2::new(1.0, 2.0)), p("b")]));
+  assert_eq!(p("#v(1.5 2)").to_string(), "#v(1.5 2)");
+  assert_eq!(p("#iv(3 4)").to_string(), "#iv(3 4)");
+  assert_ne!(p("#v(3 4)"), p("#iv(3 4)"));
+  assert_eq!(p("'#v(1 2)"), Val::List(vec![p("quote"), Val::Vec2(Vec2::new(1.0, 2.0))]));
+  assert_eq!(p("#v (1 2)"), p("#v"));
+  assert_eq!(p("#v(x 2)"), Val::Sym("Error parsing: #v(x 2)".to_string()));
+  assert_eq!(p("#iv(1.5 2)"), Val::Sym("Error parsing: #iv(1.5 2)".to_string()));
+  assert_eq!(p("(a #v(1 2 3))"), Val::Sym("Error parsing: (a #v(1 2 3))".to_string()));
+  assert_eq!(p_all("(a) #iv(1)"), Err("Malformed #iv literal".to_string()));
+  // literals evaluate to themselves and read back as written
+  assert_eq!(eval_s(&p("#iv(3 4)"), s), p("#iv(3 4)"));
+  assert_eq!(eval_s(&p("(read-from-string \"#v(0.5 1)\")"), s), p("#v(0.5 1)"));
+
+  assert_eq!(eval_s(&p("(vec2 1 2)"), s), p("#v(1 2)"));
+  assert_eq!(eval_s(&p("(ivec2 1.7 2)"), s), p("#iv(1 2)"));
+  assert_eq!(eval_s(&p("(vec2? #iv(1 2))"), s), p("t"));
+  assert_eq!(eval_s(&p("(vec2? '#iv(1 2))"), s), p("t"));
+  assert_eq!(eval_s(&p("'#v(1 2)"), s), p("#v(1 2)"));
+  assert_eq!(eval_s(&p("(read-from-string \"#v(x 2)\")"), s), Val::Sym("Error: Could not read: #v(x 2)".to_string()));
+  assert_eq!(eval_s(&p("(vec2? '(1 2))"), s), p("()"));
+  assert_eq!(eval_s(&p("(vec2-y #iv(1 2))"), s), p("2"));
+
+  // grid positions stay on the grid until mixed with floats
+  assert_eq!(eval_s(&p("(v+ #iv(1 2) #iv(3 4))"), s), p("#iv(4 6)"));
+  assert_eq!(eval_s(&p("(v+ #iv(1 2) #v(0.5 0.5))"), s), p("#v(1.5 2.5)"));
+  assert_eq!(eval_s(&p("(v- #iv(5 5) #iv(1 2))"), s), p("#iv(4 3)"));
+  assert_eq!(eval_s(&p("(v- #iv(1 -2))"), s), p("#iv(-1 2)"));
+  // grid positions stay exact past what a float holds
+  assert_eq!(p("#iv(16777217 -16777217)"), Val::IVec2(IVec2::new(16777217, -16777217)));
+  assert_eq!(eval_s(&p("(v+ #iv(16777217 0) #iv(0 0))"), s), Val::IVec2(IVec2::new(16777217, 0)));
+  assert_eq!(eval_s(&p("(v- #iv(16777219 1) #iv(2 0))"), s), Val::IVec2(IVec2::new(16777217, 1)));
+  assert_eq!(eval_s(&p("(v- #v(1 -2))"), s), p("#v(-1 2)"));
+  assert_eq!(eval_s(&p("(v* #iv(1 2) 3)"), s), p("#iv(3 6)"));
+  assert_eq!(eval_s(&p("(v* 0.5 #iv(1 2))"), s), p("#v(0.5 1)"));
+  assert_eq!(eval_s(&p("(dot #v(1 2) #v(3 4))"), s), p("11"));
+  assert_eq!(eval_s(&p("(v-length #iv(3 4))"), s), p("5"));
+  assert_eq!(eval_s(&p("(v-length '(3 4))"), s), Val::Sym("Error: v-length expects vec2s, got (3 4)".to_string()));
+  assert_eq!(eval_s(&p("(normalize #v(0 5))"), s), p("#v(0 1)"));
+  assert_eq!(eval_s(&p("(normalize #v(0 0))"), s), p("#v(0 0)"));
+  assert_eq!(eval_s(&p("(distance #iv(0 0) #iv(3 4))"), s), p("5"));
+  assert_eq!(eval_s(&p("(manhattan #iv(0 0) #iv(3 -4))"), s), p("7"));
+
+  assert_eq!(eval_s(&p("(v+ #v(1 2) '(1 2))"), s), Val::Sym("Error: v+ expects vec2s, got (1 2)".to_string()));
+  assert_eq!(eval_s(&p("(v* #v(1 2) #v(1 2))"), s), Val::Sym("Error: v* expects a vec2 and a number".to_string()));
+  assert_eq!(eval_s(&p("(distance #v(1 2))"), s), Val::Sym("Error: distance expects 2 arguments, got 1".to_string()));
+
+  assert_eq!(read_vec2(&p("#iv(3 4)")), Some(Vec2::new(3.0, 4.0)));
+  assert_eq!(read_vec2(&p("(0.5 1)")), Some(Vec2::new(0.5, 1.0)));
+  assert_eq!(read_ivec2(&p("#v(1.5 -2.5)")), Some(IVec2::new(1, -2)));
+  assert_eq!(read_ivec2(&p("5")), None);
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "lists"))]
+fn test_list_functions() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  assert_eq!(eval_s(&p("(map (lambda (x) (* 2 x)) '(1 2 3 4 5))"), s), p("(2 4 6 8 10)"));
+  assert_eq!(eval_s(&p("(map car '((a 1) (b 2)))"), s), p("(a b)"));
+  // items are passed as values, not evaluated again
+  assert_eq!(eval_s(&p("(map (lambda (x) x) '((+ 1 2) undefined))"), s), p("((+ 1 2) undefined)"));
+  assert_eq!(eval_s(&p("(map car ())"), s), p("()"));
+  assert_eq!(eval_s(&p("(for-each car '((a) (b)))"), s), p("()"));
+  assert_eq!(eval_s(&p("(filter odd? (range 10))"), s), p("(1 3 5 7 9)"));
+  assert_eq!(eval_s(&p("(fold + 0 '(1 2 3 4))"), s), p("10"));
+  assert_eq!(eval_s(&p("(fold (lambda (acc x) (cons x acc)) () '(1 2 3))"), s), p("(3 2 1)"));
+  assert_eq!(eval_s(&p("(fold + 5 ())"), s), p("5"));
+
+  assert_eq!(eval_s(&p("(sort '(5 3 9 1 3 7) <)"), s), p("(1 3 3 5 7 9)"));
+  assert_eq!(eval_s(&p("(sort () <)"), s), p("()"));
+  assert_eq!(eval_s(&p("(sort '(4) <)"), s), p("(4)"));
+  // equal items keep their order
+  assert_eq!(eval_s(&p("(sort '((b 2) (a 1) (c 2) (d 1) (e 0)) (lambda (x y) (< (nth 1 x) (nth 1 y))))"), s), p("((e 0) (a 1) (d 1) (b 2) (c 2))"));
+
+  assert_eq!(eval_s(&p("(length '(a b c))"), s), p("3"));
+  // a step doesn't copy the list, so long lists take linear time, past
+  // the steps eval_s allows
+  for (program, expected) in [
+    ("(length (filter odd? (map (lambda (x) (+ x 1)) (range 50000))))", "25000"),
+    ("(fold (lambda (acc x) (+ acc 1)) 0 (range 50000))", "50000"),
+    ("(= (sort (reverse (range 5000)) <) (range 5000))", "t"),
+  ] {
+    s.set_program(p(program));
+    let result = loop {
+      if let Some(result) = s.step() {
+        break result;
+      }
+    };
+    assert_eq!(result, p(expected));
+  }
+  assert_eq!(eval_s(&p("(append '(1 2) () '(3))"), s), p("(1 2 3)"));
+  assert_eq!(eval_s(&p("(reverse '(1 2 3))"), s), p("(3 2 1)"));
+  assert_eq!(eval_s(&p("(nth 1 '(a b c))"), s), p("b"));
+  assert_eq!(eval_s(&p("(nth 5 '(a b c))"), s), p("()"));
+  assert_eq!(eval_s(&p("(member 'b '(a b c))"), s), p("(b c)"));
+  assert_eq!(eval_s(&p("(member 'z '(a b c))"), s), p("()"));
+  assert_eq!(eval_s(&p("(assoc 'hp '((name tank) (hp 100)))"), s), p("(hp 100)"));
+  assert_eq!(eval_s(&p("(range 2 5)"), s), p("(2 3 4)"));
+  assert_eq!(eval_s(&p("(range 10 0 -3)"), s), p("(10 7 4 1)"));
+
+  assert_eq!(eval_s(&p("(map 3 '(1 2))"), s), Val::Sym("Error: map expects a function and a list".to_string()));
+  assert_eq!(eval_s(&p("(range 0 5 0)"), s), Val::Sym("Error: range step can't be 0".to_string()));
+  assert_eq!(eval_s(&p("(range 0 1e12)"), s), Val::Sym("Error: range would be longer than 1000000 numbers".to_string()));
+  assert_eq!(eval_s(&p("(range 1e39)"), s), Val::Sym("Error: range expects finite numbers".to_string()));
+  assert_eq!(eval_s(&p("(length (range 1e6 0 -1))"), s), p("1000000"));
+  assert_eq!(eval_s(&p("(length 5)"), s), Val::Sym("Error: length expects a list".to_string()));
+
+  // the function runs on the stack machine, so it can wait on messages
+  s.message_add("ask");
+  s.set_program(p("(map (lambda (x) (+ x (ask x))) '(1 2 3))"));
+  for i in 1..=3 {
+    s.run();
+    assert_eq!(s.message_peek(), Some(vec![p("ask"), Val::Num(i as f32)]));
+    s.message_return(Val::Num(10.0));
+  }
+  s.run();
+  assert_eq!(s.result, p("(11 12 13)"));
+
+  s.set_program(p("(for-each ask '(a b))"));
+  for item in ["a", "b"] {
+    s.run();
+    assert_eq!(s.message_peek(), Some(vec![p("ask"), p(item)]));
+    s.message_return(Val::nil());
+  }
+  s.run();
+  assert_eq!(s.result, p("()"));
+
+  // and is stopped by the step budget like any other loop
+  s.set_program(p("(for-each (lambda (x) (loop)) '(1 2))"));
+  assert!(matches!(s.run_for(1000), RunOutcome::Paused | RunOutcome::Cancelled(_)));
+}
+
+#[test]
+#[cfg(feature = "lists")]
+fn test_object_builtins() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  eval_s(&p("(define unit '((name tank) (size 1 1) (very-cool) () (hp 50) (hp 100)))"), s);
+  assert_eq!(eval_s(&p("(obj-get unit 'name)"), s), p("tank"));
+  // the same three shapes read_object gives the host
+  assert_eq!(eval_s(&p("(obj-get unit 'size)"), s), p("(1 1)"));
+  assert_eq!(eval_s(&p("(obj-get unit 'very-cool)"), s), p("()"));
+  assert_eq!(eval_s(&p("(obj-get unit 'hp)"), s), p("100"));
+  assert_eq!(eval_s(&p("(obj-get unit 'speed)"), s), p("()"));
+  assert_eq!(eval_s(&p("(obj-get unit 'speed 0.5)"), s), p("0.5"));
+  assert_eq!(eval_s(&p("(obj-get unit #:name)"), s), p("tank"));
+  assert_eq!(eval_s(&p("(obj-has? unit 'very-cool)"), s), p("t"));
+  assert_eq!(eval_s(&p("(obj-has? unit 'speed)"), s), p("()"));
+  assert_eq!(eval_s(&p("(obj-keys unit)"), s), p("(name size very-cool hp)"));
+
+  assert_eq!(eval_s(&p("(obj-set unit 'hp 75)"), s), p("((name tank) (size 1 1) (very-cool) () (hp 75))"));
+  assert_eq!(eval_s(&p("(obj-set '((a 1)) 'pos '(3 4))"), s), p("((a 1) (pos 3 4))"));
+  assert_eq!(eval_s(&p("(obj-get (obj-set '() 'tags '(x)) 'tags)"), s), p("(x)"));
+  assert_eq!(eval_s(&p("(obj-merge '((a 1) (b 2)) '((b 3) (c)) '((a 4)))"), s), p("((a 4) (b 3) (c))"));
+
+  // whatever a script writes, the host reads back the same way
+  let written = eval_s(&p("(obj-set (obj-set (obj-set () 'size '(2 3)) 'one '(x)) 'flag ())"), s);
+  let mut read = vec![];
+  read_object(&written, |key, val| read.push((key.to_string(), val.clone())));
+  assert_eq!(read, vec![("size".to_string(), p("(2 3)")), ("one".to_string(), p("(x)")), ("flag".to_string(), p("()"))]);
+
+  assert_eq!(eval_s(&p("(obj-get '((1 a)) 'a)"), s), Val::Sym("Error: Invalid object property key".to_string()));
+  assert_eq!(eval_s(&p("(obj-keys '(a))"), s), Val::Sym("Error: Invalid object property".to_string()));
+  assert_eq!(eval_s(&p("(obj-get 5 'a)"), s), Val::Sym("Error: Invalid object".to_string()));
+  assert_eq!(eval_s(&p("(obj-get unit \"name\")"), s), Val::Sym("Error: obj-get expects a symbol key".to_string()));
+}
+
+#[test]
+#[cfg(all(feature = "math", feature = "lists"))]
+fn test_records() {
+  let mut state = State::new();
+  let s = &mut state;
+  s.load_lib();
+
+  assert_eq!(eval_s(&p("(define-record point (x y))"), s), p("point"));
+  eval_s(&p("(define a (make-point 1 2))"), s);
+  assert_eq!(eval_s(&p("a"), s), Val::Record(Record {
+    name: "point".to_string(),
+    fields: vec![("x".to_string(), p("1")), ("y".to_string(), p("2"))],
+  }));
+  assert_eq!(eval_s(&p("(point-y a)"), s), p("2"));
+  assert_eq!(eval_s(&p("(point? a)"), s), p("t"));
+  assert_eq!(eval_s(&p("(point? '(1 2))"), s), p("()"));
+  assert_eq!(eval_s(&p("(record? a)"), s), p("t"));
+  // structural equality
+  assert_eq!(eval_s(&p("(= a (make-point 1 2))"), s), p("t"));
+  assert_eq!(eval_s(&p("(= a (make-point 2 1))"), s), p("()"));
+
+  // printed records read back as equal values
+  assert_eq!(eval_s(&p("a"), s).to_string(), "#record(point (x 1) (y 2))");
+  assert_eq!(p("#record(point (x 1) (y 2))"), eval_s(&p("a"), s));
+  assert_eq!(eval_s(&p("(make-point '(a b) \"s\")"), s).to_string(), "#record(point (x (a b)) (y \"s\"))");
+  assert_eq!(eval_s(&p("(point-y '#record(point (x 1) (y 2)))"), s), p("2"));
+  assert_eq!(eval_s(&p("(record? (car '(#record(point (x 1)))))"), s), p("t"));
+  assert_eq!(p("#record((x 1))"), Val::Sym("Error parsing: #record((x 1))".to_string()));
+  assert_eq!(p("#record(point x)"), Val::Sym("Error parsing: #record(point x)".to_string()));
+
+  eval_s(&p("(define-record unit (name pos tags))"), s);
+  assert_eq!(eval_s(&p("(point? (make-unit 'tank 1 ()))"), s), p("()"));
+  assert_eq!(eval_s(&p("(record->object (make-unit 'tank '(3 4) '(x)))"), s), p("((name tank) (pos 3 4) (tags (x)))"));
+  assert_eq!(eval_s(&p("(object->record 'unit '((pos 3 4) (name tank) (extra 1)))"), s), eval_s(&p("(make-unit 'tank '(3 4) ())"), s));
+  assert_eq!(eval_s(&p("(unit-pos (object->record 'unit (record->object (make-unit 'tank '(3 4) '(x)))))"), s), p("(3 4)"));
+  // closures kept in records keep their scope
+  eval_s(&p("(define-record counter (step))"), s);
+  eval_s(&p("(define (make-adder n) (make-counter (lambda (x) (+ x n))))"), s);
+  assert_eq!(eval_s(&p("((counter-step (make-adder 5)) 1)"), s), p("6"));
+  assert_eq!(s.record_fields("unit"), Some(&vec!["name".to_string(), "pos".to_string(), "tags".to_string()]));
+
+  assert_eq!(eval_s(&p("(make-point 1)"), s), Val::Sym("Error: make-point expects 2 arguments, got 1".to_string()));
+  assert_eq!(eval_s(&p("(point-x (make-unit 1 2 3))"), s), Val::Sym("Error: point-x expects a point, got #record(unit (name 1) (pos 2) (tags 3))".to_string()));
+  assert_eq!(eval_s(&p("(object->record 'nope ())"), s), Val::Sym("Error: Unknown record type: nope".to_string()));
+  assert_eq!(eval_s(&p("(define-record bad (1 2))"), s), Val::Sym("Error: define-record fields must be symbols".to_string()));
+}
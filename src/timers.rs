@@ -0,0 +1,151 @@
+use crate::{val::Val, exec::State};
+
+// enough slots that most timers land in a slot they are due in
+const WHEEL_SLOTS: usize = 64;
+
+#[derive(Clone, Debug)]
+pub struct Timer {
+  pub id: usize,
+  pub due: usize,
+  pub period: Option<usize>,
+  pub f: Val,
+}
+
+/// Timers hashed into slots by the tick they are due. Advancing a tick
+/// only looks at one slot.
+#[derive(Clone, Debug)]
+pub struct TimerWheel {
+  pub now: usize,
+  slots: Vec<Vec<Timer>>,
+  next_id: usize,
+}
+
+impl Default for TimerWheel {
+  fn default() -> TimerWheel {
+    TimerWheel {
+      now: 0,
+      slots: vec![vec![]; WHEEL_SLOTS],
+      next_id: 0,
+    }
+  }
+}
+
+impl TimerWheel {
+  fn insert(&mut self, timer: Timer) {
+    self.slots[timer.due % WHEEL_SLOTS].push(timer);
+  }
+
+  /// Schedules `f` in `delay` ticks, and every `period` ticks after that
+  /// if given. Returns the id of the timer.
+  pub fn add(&mut self, delay: usize, period: Option<usize>, f: Val) -> usize {
+    let id = self.next_id;
+    self.next_id += 1;
+    self.insert(Timer {
+      id,
+      // a delay too long to count to is never due
+      due: self.now.saturating_add(delay.max(1)),
+      period: period.map(|period| period.max(1)),
+      f,
+    });
+    id
+  }
+
+  /// Unschedules a timer, returning it if it was still scheduled.
+  pub fn cancel(&mut self, id: usize) -> Option<Timer> {
+    for slot in self.slots.iter_mut() {
+      if let Some(i) = slot.iter().position(|timer| timer.id == id) {
+        return Some(slot.remove(i));
+      }
+    }
+    None
+  }
+
+  /// The callbacks of every scheduled timer.
+  pub fn callbacks(&self) -> impl Iterator<Item = &Val> {
+    self.slots.iter().flatten().map(|timer| &timer.f)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.slots.iter().all(|slot| slot.is_empty())
+  }
+
+  /// Advances one tick, returning the timers due then in the order they
+  /// were made. Repeating timers are scheduled again.
+  pub fn advance(&mut self) -> Vec<Timer> {
+    self.now += 1;
+    let now = self.now;
+    let slot = &mut self.slots[now % WHEEL_SLOTS];
+    let (mut due, rest): (Vec<Timer>, Vec<Timer>) = slot.drain(..).partition(|timer| timer.due == now);
+    *slot = rest;
+    due.sort_by_key(|timer| timer.id);
+
+    for timer in due.iter() {
+      if let Some(period) = timer.period {
+        self.insert(Timer {
+          due: now.saturating_add(period),
+          ..timer.clone()
+        });
+      }
+    }
+    due
+  }
+}
+
+impl State {
+  /// Calls `f` with no arguments once `delay` ticks have passed, and every
+  /// `period` ticks after that if given. Returns a handle for
+  /// `cancel_timer`.
+  pub fn add_timer(&mut self, delay: usize, period: Option<usize>, f: Val) -> Val {
+    // held until the timer is cancelled or fires for the last time
+    if let Val::Lambda(_, scope, _) = &f {
+      self.vars.pin(*scope);
+    }
+    Val::Timer(self.timers.add(delay, period, f))
+  }
+
+  /// Stops a timer, returning whether it was still scheduled.
+  pub fn cancel_timer(&mut self, timer: &Val) -> bool {
+    let Val::Timer(id) = timer else {
+      return false;
+    };
+    match self.timers.cancel(*id) {
+      Some(timer) => {
+        self.release_timer(timer);
+        true
+      },
+      None => false,
+    }
+  }
+
+  fn release_timer(&mut self, timer: Timer) {
+    if let Val::Lambda(_, scope, _) = timer.f {
+      self.unpin_scope(scope);
+    }
+  }
+
+  /// The number of ticks passed so far.
+  pub fn ticks(&self) -> usize {
+    self.timers.now
+  }
+
+  /// Advances time by `n` ticks, queueing the callbacks of due timers as
+  /// interrupts from the "timer" source. Returns how many were queued.
+  pub fn tick(&mut self, n: usize) -> usize {
+    let mut queued = 0;
+    for i in 0..n {
+      if self.timers.is_empty() {
+        self.timers.now += n - i;
+        break;
+      }
+      for timer in self.timers.advance() {
+        self.queue_interrupt("timer", Val::List(vec![timer.f.clone()]));
+        queued += 1;
+        // the queued call keeps the closure alive until it has run
+        if timer.period.is_none() {
+          self.release_timer(timer);
+        }
+      }
+    }
+    queued
+  }
+}
@@ -0,0 +1,76 @@
+use crate::{val::Val, exec::{State, Stackframe}, generator::generator_id, builtins::{protect_frame_cb, return_first_cb, unwind_resume_cb, is_builtin}};
+
+// a protect frame is (protect id before (cleanup...) result) and stays at
+// pc 4 while its body runs
+const PROTECT_PC: usize = 4;
+
+/// The id of a frame set up by `unwind-protect` or `dynamic-wind` that is
+/// still running its body.
+pub(crate) fn protect_id(frame: &Stackframe) -> Option<usize> {
+  if frame.pc != PROTECT_PC || !is_builtin(&frame.accum[0], protect_frame_cb) {
+    return None;
+  }
+  match frame.accum[1] {
+    Val::Marker(id) => Some(id),
+    _ => None,
+  }
+}
+
+impl State {
+  /// Turns the top frame into a protect frame that evaluates `body`, then
+  /// runs `cleanup` however the body is left. `before` is called again
+  /// whenever a continuation jumps back into the body.
+  pub fn add_protect_frame(&mut self, before: Val, body: Val, cleanup: Vec<Val>) {
+    self.marker_count += 1;
+    self.replace_stackframe(vec![
+      Val::Builtin(false, protect_frame_cb),
+      Val::Marker(self.marker_count),
+      before,
+      Val::List(cleanup),
+      Val::nil(),
+    ]);
+    self.get_stackframe().pc = PROTECT_PC;
+    self.add_stackframe(vec![Val::Sym("do".to_string()), body]);
+  }
+
+  /// Replaces a protect frame whose body returned `val` with one running
+  /// its cleanup forms before returning `val`.
+  pub(crate) fn finish_protect_frame(&mut self, cleanup: Vec<Val>, val: Val) {
+    let mut list = vec![Val::Builtin(false, return_first_cb), val];
+    list.extend(cleanup);
+    self.replace_stackframe(list);
+    self.get_stackframe().pc = 2;
+  }
+
+  /// Drops frames until `depth` are left, as part of a non-local exit.
+  /// The exit is given as `transfer`, a call that redoes it from the top
+  /// frame. Protect frames not listed in `keep` stop the unwinding: the
+  /// frame runs its cleanup, then calls `transfer` to carry on. Generators
+  /// whose frames are dropped are finished, unless listed in `keep`.
+  /// Returns whether `depth` was reached.
+  pub(crate) fn unwind(&mut self, depth: usize, transfer: Vec<Val>, val: &Val, keep: &[usize]) -> bool {
+    while self.stack.len() > depth {
+      let frame = self.stack.last().unwrap();
+      if let Some(id) = protect_id(frame) {
+        if !keep.contains(&id) {
+          let cleanup = match &frame.accum[3] {
+            Val::List(cleanup) => cleanup.clone(),
+            _ => vec![],
+          };
+          let mut list = vec![Val::Builtin(false, unwind_resume_cb), Val::List(transfer)];
+          list.extend(cleanup);
+          self.replace_stackframe(list);
+          self.get_stackframe().pc = 2;
+          return false;
+        }
+      }
+      if let Some(id) = generator_id(frame) {
+        if !keep.contains(&id) {
+          self.finish_generator(id);
+        }
+      }
+      self.pop_stackframe(val);
+    }
+    true
+  }
+}
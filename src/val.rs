@@ -1,18 +1,28 @@
 
-use rust_lisp::{parser::parse, model::{Value, Symbol}};
 use std::fmt::Debug;
 
-use crate::{exec::State, variables::ScopeRef};
+use crate::{reader::read_all, exec::{State, Stackframe}, variables::ScopeRef, vec2::{Vec2, IVec2}, record::Record};
 
 #[derive(Clone)]
 pub enum Val {
   Sym(String),
+  Keyword(String),
   String(String),
+  Char(char),
   Num(f32),
+  Vec2(Vec2),
+  IVec2(IVec2),
   List(Vec<Val>),
   Builtin(bool, fn(Vec<Val>, &mut State)),
   Lambda(bool, ScopeRef, Vec<Val>),
   Message(String),
+  Continuation(usize, Vec<Stackframe>),
+  Escape(usize),
+  Generator(usize),
+  Timer(usize),
+  Subscription(usize),
+  Marker(usize),
+  Record(Record),
 }
 
 impl Val {
@@ -47,6 +57,11 @@ impl Val {
         }
       },
       Val::Builtin(_, _) => true,
+      Val::Lambda(_, _, _) => true,
+      Val::Continuation(_, _) => true,
+      Val::Escape(_) => true,
+      Val::Generator(_) => true,
+      Val::Message(_) => true,
       _ => false,
     }
   }
@@ -54,8 +69,12 @@ impl Val {
   pub(crate) fn memory_usage(&self) -> usize {
     match self {
       Val::Sym(sym) => sym.len(),
+      Val::Keyword(key) => key.len(),
       Val::String(string) => string.len(),
+      Val::Char(_) => 4,
       Val::Num(_) => 4,
+      Val::Vec2(_) => 8,
+      Val::IVec2(_) => 8,
       Val::List(list) => {
         let mut size = 4;
         for val in list {
@@ -72,6 +91,13 @@ impl Val {
         size
       },
       Val::Message(message) => message.len(),
+      Val::Continuation(_, stack) => stack.iter().map(|frame| frame.memory_usage()).sum(),
+      Val::Escape(_) => 4,
+      Val::Generator(_) => 4,
+      Val::Timer(_) => 4,
+      Val::Subscription(_) => 4,
+      Val::Marker(_) => 4,
+      Val::Record(record) => record.name.len() + record.fields.iter().map(|(_, val)| val.memory_usage()).sum::<usize>(),
     }
   }
 }
@@ -82,10 +108,21 @@ impl ToString for Val {
       Val::Sym(sym) => {
         sym.to_string()
       },
+      Val::Keyword(key) => {
+        format!("#:{}", key)
+      },
       Val::String(string) => {
         format!("\"{}\"", string)
       },
+      Val::Char(c) => {
+        match char_name(*c) {
+          Some(name) => format!("#\\{}", name),
+          None => format!("#\\{}", c),
+        }
+      },
       Val::Num(num) => num.to_string(),
+      Val::Vec2(v) => format!("#v({} {})", v.x, v.y),
+      Val::IVec2(v) => format!("#iv({} {})", v.x, v.y),
       Val::List(list) => {
         let mut s = String::new();
         s.push('(');
@@ -120,6 +157,20 @@ impl ToString for Val {
       Val::Message(message) => {
         message.to_string()
       },
+      Val::Continuation(id, _) => format!("<continuation{}>", id),
+      Val::Escape(id) => format!("<escape{}>", id),
+      Val::Generator(id) => format!("<generator{}>", id),
+      Val::Timer(id) => format!("<timer{}>", id),
+      Val::Subscription(id) => format!("<subscription{}>", id),
+      Val::Marker(id) => format!("<marker{}>", id),
+      Val::Record(record) => {
+        let mut s = format!("#record({}", record.name);
+        for (field, val) in &record.fields {
+          s.push_str(&format!(" ({} {})", field, val.to_string()));
+        }
+        s.push(')');
+        s
+      },
     }
   }
 }
@@ -141,78 +192,64 @@ impl PartialEq for Val {
 
     match (self, other) {
       (Val::Sym(sym1), Val::Sym(sym2)) => sym1 == sym2,
+      (Val::Keyword(key1), Val::Keyword(key2)) => key1 == key2,
       (Val::String(string1), Val::String(string2)) => string1 == string2,
       (Val::Sym(_), Val::String(_)) => false,
       (Val::String(_), Val::Sym(_)) => false,
+      (Val::Char(c1), Val::Char(c2)) => c1 == c2,
       (Val::Num(num1), Val::Num(num2)) => num1 == num2,
+      (Val::Vec2(v1), Val::Vec2(v2)) => v1 == v2,
+      (Val::IVec2(v1), Val::IVec2(v2)) => v1 == v2,
       (Val::List(list1), Val::List(list2)) => list1 == list2,
       (Val::Lambda(_, _, list1), Val::Lambda(_, _, list2)) => list1 == list2,
       (Val::Lambda(_, _, list1), Val::List(list2)) => list1 == list2,
       (Val::List(list1), Val::Lambda(_, _, list2)) => list1 == list2,
+      (Val::Continuation(id1, _), Val::Continuation(id2, _)) => id1 == id2,
+      (Val::Escape(id1), Val::Escape(id2)) => id1 == id2,
+      (Val::Generator(id1), Val::Generator(id2)) => id1 == id2,
+      (Val::Timer(id1), Val::Timer(id2)) => id1 == id2,
+      (Val::Subscription(id1), Val::Subscription(id2)) => id1 == id2,
+      (Val::Marker(id1), Val::Marker(id2)) => id1 == id2,
+      (Val::Record(record1), Val::Record(record2)) => record1 == record2,
       _ => false,
     }
   }
 }
 
+/// Reads the first expression in `s`, or an `Error parsing` symbol if
+/// there is none.
 pub fn p(s: &str) -> Val {
-  let mut ast_iter = parse(&s);
-  let ast = ast_iter.next(); 
-  if ast.is_none() {
-    let error = format!("Error parsing: {}", s);
-    return Val::Sym(error);
-  }
-  let ast_inner = ast.unwrap();
-  if ast_inner.is_err() {
-    let error = format!("Error parsing: {}", s);
-    return Val::Sym(error);
+  match read_all(s) {
+    (vals, _) if !vals.is_empty() => vals.into_iter().next().unwrap(),
+    _ => Val::Sym(format!("Error parsing: {}", s)),
   }
-  let first_expression = ast_inner.unwrap();
-
-  value_to_val(first_expression)
 }
 
-pub fn p_all(s: &str) -> Vec<Val> {
-  let mut ast_iter = parse(&s);
-  let mut result = vec![];
-  loop {
-    let ast = ast_iter.next(); 
-    if ast.is_none() {
-      return result;
-    }
-    let ast_inner = ast.unwrap();
-    if ast_inner.is_err() {
-      continue;
-    }
-    let expression = ast_inner.unwrap();
-  
-    result.push(value_to_val(expression));
+/// Reads every expression in `s`, or the error that stopped the reader.
+pub fn p_all(s: &str) -> Result<Vec<Val>, String> {
+  match read_all(s) {
+    (vals, None) => Ok(vals),
+    (_, Some(error)) => Err(error),
   }
 }
 
-fn value_to_val(value: Value) -> Val {
-  match value {
-    Value::Symbol(Symbol(name)) => {
-      //catch a bug in the parser
-      if name == "\"\"".to_owned() {
-        Val::String("".to_string())
-      } else {
-        Val::Sym(name)
-      }
-    },
-    Value::String(string) => Val::String(string),
-    Value::List(list) => Val::List(list.into_iter().map(value_to_val).collect()),
-    Value::Int(int) => Val::Num(int as f32),
-    Value::Float(float) => Val::Num(float),
-    Value::True => Val::Sym("t".to_string()),
-    Value::False => Val::Sym("f".to_string()),
-    //Value::HashMap(hash_map) => SValue::List(SList { list: hash_map.into_iter().map(|(key, value)| SValue::List(SList { list: vec![value_to_svalue(key), value_to_svalue(value)] })).collect() }),
-    //Value::NativeFunction(native_function) => SValue::Symbol(native_function.name.clone()),
-    //Value::Lambda(lambda) => SValue::List(SList { list: vec![SValue::Symbol("lambda".to_string()), SValue::List(SList { list: lambda.args.into_iter().map(value_to_svalue).collect() }), value_to_svalue(lambda.body)] }),
-    Value::Lambda(_) => Val::Sym("lambda".to_string()),
-    Value::HashMap(_) => Val::Sym("hashmap".to_string()),
-    Value::NativeFunc(_) => Val::Sym("nativefunc".to_string()),
-    Value::Macro(_) => Val::Sym("macro".to_string()),
-    Value::Foreign(_) => Val::Sym("foreign".to_string()),
-    Value::TailCall {func: _, args: _} => Val::Sym("tailcall".to_string()),
+const CHAR_NAMES: [(char, &str); 5] = [
+  (' ', "space"),
+  ('\n', "newline"),
+  ('\t', "tab"),
+  ('\r', "return"),
+  ('\0', "nul"),
+];
+
+fn char_name(c: char) -> Option<&'static str> {
+  CHAR_NAMES.iter().find(|(named, _)| *named == c).map(|(_, name)| *name)
+}
+
+/// Reads what follows `#\` in a char literal: one char or a char name.
+pub(crate) fn read_char(text: &str) -> Option<char> {
+  let mut chars = text.chars();
+  match (chars.next(), chars.next()) {
+    (Some(c), None) => Some(c),
+    _ => CHAR_NAMES.iter().find(|(_, name)| *name == text).map(|(c, _)| *c),
   }
 }
@@ -1,6 +1,6 @@
 use std::collections::HashMap;
 
-use crate::val::Val;
+use crate::{val::Val, exec::Stackframe};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
 pub struct ScopeRef(pub usize);
@@ -15,6 +15,9 @@ pub struct Scope {
 pub struct VarSpace {
   scopes: Vec<Scope>,
   free_scopes: Vec<ScopeRef>,
+  pinned: HashMap<ScopeRef, usize>,
+  // scopes whose last pin was released, waiting to be freed
+  pub(crate) unpinned: Vec<ScopeRef>,
 }
 
 impl VarSpace {
@@ -25,6 +28,8 @@ impl VarSpace {
         parent: ScopeRef(0),
       }],
       free_scopes: vec![],
+      pinned: HashMap::new(),
+      unpinned: vec![],
     }
   }
 
@@ -101,7 +106,7 @@ impl VarSpace {
     let mut list = None;
     match val {
       Val::Lambda(_, var_ref, llist) => {
-        if self.scope_has_ancestor(scope, *var_ref) {
+        if self.scope_has_ancestor(*var_ref, scope) {
           return true;
         }
         list = Some(llist);
@@ -109,6 +114,13 @@ impl VarSpace {
       Val::List(llist) => {
         list = Some(llist);
       },
+      Val::Record(record) => {
+        return record.fields.iter().any(|(_, val)| self.val_has_ancestor(scope, val));
+      },
+      // a continuation needs the scopes of its frames once resumed
+      Val::Continuation(_, stack) => {
+        return stack.iter().any(|frame| self.frame_has_ancestor(scope, frame));
+      },
       _ => {},
     }
 
@@ -123,6 +135,25 @@ impl VarSpace {
     false
   }
 
+  /// Whether a frame runs in `scope` or a scope under it, or holds a value
+  /// that refers to one.
+  pub fn frame_has_ancestor(&self, scope: ScopeRef, frame: &Stackframe) -> bool {
+    self.scope_has_ancestor(frame.vars, scope) ||
+      frame.retained.iter().any(|retained| self.scope_has_ancestor(*retained, scope)) ||
+      frame.accum.iter().any(|val| self.val_has_ancestor(scope, val))
+  }
+
+  /// Whether a variable outside `scope` and the scopes under it holds a
+  /// value that refers to one of them.
+  pub fn vars_have_ancestor(&self, scope: ScopeRef) -> bool {
+    self.scopes.iter().enumerate().any(|(i, s)| {
+      let i = ScopeRef(i);
+      !self.free_scopes.contains(&i) &&
+        !self.scope_has_ancestor(i, scope) &&
+        s.vars.values().any(|val| self.val_has_ancestor(scope, val))
+    })
+  }
+
   fn scope_has_ancestor(&self, scope: ScopeRef, ancestor: ScopeRef) -> bool {
     let mut s = scope;
     loop {
@@ -141,13 +172,60 @@ impl VarSpace {
   }
 
   pub fn remove_inner(&mut self, scope: ScopeRef) {
-    let mut s = &mut self.scopes[scope.0];
+    let s = &mut self.scopes[scope.0];
     s.vars.clear();
     s.parent = ScopeRef(0);
     self.free_scopes.push(scope);
+    self.unpinned.retain(|unpinned| *unpinned != scope);
+  }
+
+  /// The scope and its ancestors, up to but not including the root.
+  fn ancestors(&self, scope: ScopeRef) -> Vec<ScopeRef> {
+    let mut chain = vec![];
+    let mut s = scope;
+    while s.0 != 0 && !chain.contains(&s) {
+      chain.push(s);
+      s = self.scopes[s.0].parent;
+    }
+    chain
+  }
+
+  /// Keeps a scope and its ancestors alive until as many `unpin`s, for
+  /// closures the host or the state holds on to after the frame that made
+  /// them has returned, such as event handlers and timers.
+  pub fn pin(&mut self, scope: ScopeRef) {
+    for s in self.ancestors(scope) {
+      *self.pinned.entry(s).or_default() += 1;
+      self.unpinned.retain(|unpinned| *unpinned != s);
+    }
+  }
+
+  /// Releases a `pin`. Scopes no longer pinned are noted, so
+  /// `State::collect_unpinned` can free the ones nothing else uses.
+  pub fn unpin(&mut self, scope: ScopeRef) {
+    for s in self.ancestors(scope) {
+      let Some(count) = self.pinned.get_mut(&s) else { continue };
+      *count -= 1;
+      if *count == 0 {
+        self.pinned.remove(&s);
+        self.unpinned.push(s);
+      }
+    }
+  }
+
+  pub fn is_pinned(&self, scope: ScopeRef) -> bool {
+    self.pinned.contains_key(&scope)
+  }
+
+  pub(crate) fn is_free(&self, scope: ScopeRef) -> bool {
+    self.free_scopes.contains(&scope)
   }
 
   pub fn remove(&mut self, scope: ScopeRef) {
+    // removing a scope takes its descendants with it, so it may already be gone
+    if scope == self.root() || self.free_scopes.contains(&scope) || self.pinned.contains_key(&scope) {
+      return;
+    }
     self.remove_inner(scope);
     let to_remove = (0..self.scopes.len())
       .map(|i| ScopeRef(i))
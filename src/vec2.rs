@@ -0,0 +1,107 @@
+use std::ops::{Add, Sub, Mul};
+
+/// A position or direction in float coordinates.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Vec2 {
+  pub x: f32,
+  pub y: f32,
+}
+
+/// A position on a grid.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct IVec2 {
+  pub x: i32,
+  pub y: i32,
+}
+
+impl Vec2 {
+  pub fn new(x: f32, y: f32) -> Vec2 {
+    Vec2 { x, y }
+  }
+
+  pub fn dot(self, other: Vec2) -> f32 {
+    self.x * other.x + self.y * other.y
+  }
+
+  pub fn length(self) -> f32 {
+    self.dot(self).sqrt()
+  }
+
+  /// The unit vector in the same direction, or zero for a zero vector.
+  pub fn normalize(self) -> Vec2 {
+    let length = self.length();
+    if length == 0.0 {
+      self
+    } else {
+      self * (1.0 / length)
+    }
+  }
+
+  pub fn distance(self, other: Vec2) -> f32 {
+    (other - self).length()
+  }
+
+  pub fn manhattan(self, other: Vec2) -> f32 {
+    (other.x - self.x).abs() + (other.y - self.y).abs()
+  }
+}
+
+impl IVec2 {
+  pub fn new(x: i32, y: i32) -> IVec2 {
+    IVec2 { x, y }
+  }
+}
+
+impl From<IVec2> for Vec2 {
+  fn from(v: IVec2) -> Vec2 {
+    Vec2::new(v.x as f32, v.y as f32)
+  }
+}
+
+impl Add for Vec2 {
+  type Output = Vec2;
+
+  fn add(self, other: Vec2) -> Vec2 {
+    Vec2::new(self.x + other.x, self.y + other.y)
+  }
+}
+
+impl Sub for Vec2 {
+  type Output = Vec2;
+
+  fn sub(self, other: Vec2) -> Vec2 {
+    Vec2::new(self.x - other.x, self.y - other.y)
+  }
+}
+
+impl Mul<f32> for Vec2 {
+  type Output = Vec2;
+
+  fn mul(self, scale: f32) -> Vec2 {
+    Vec2::new(self.x * scale, self.y * scale)
+  }
+}
+
+impl Add for IVec2 {
+  type Output = IVec2;
+
+  fn add(self, other: IVec2) -> IVec2 {
+    IVec2::new(self.x.wrapping_add(other.x), self.y.wrapping_add(other.y))
+  }
+}
+
+impl Sub for IVec2 {
+  type Output = IVec2;
+
+  fn sub(self, other: IVec2) -> IVec2 {
+    IVec2::new(self.x.wrapping_sub(other.x), self.y.wrapping_sub(other.y))
+  }
+}
+
+impl Mul<i32> for IVec2 {
+  type Output = IVec2;
+
+  fn mul(self, scale: i32) -> IVec2 {
+    IVec2::new(self.x.wrapping_mul(scale), self.y.wrapping_mul(scale))
+  }
+}